@@ -1,11 +1,30 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
 
-use crate::utils::Terminal;
+use crate::{
+    no_proxy::{Dialect, Tool},
+    utils::{Format, Terminal},
+};
+
+/// Default port of `serve`.
+pub const LOCAL_PORT: u16 = 3128;
 
 #[derive(Parser, Debug)]
 pub struct Args {
     #[clap(subcommand)]
     pub command: Commands,
+
+    /// Configuration file [default: $XDG_CONFIG_HOME/termproxy/config.toml]
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+
+    /// Profile from the configuration file to apply
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Print proxy passwords and URL tokens instead of masking them
+    #[arg(long, global = true)]
+    pub show_secrets: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -19,7 +38,120 @@ pub enum Commands {
     },
 
     /// Get current terminal's proxy environment variables
-    Cat,
+    Cat {
+        /// Print the variables in a machine-readable format
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+    },
+
+    /// Clear proxy environment variables with specified terminal
+    Off {
+        terminal: Terminal,
+
+        /// Output to file
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Keep a file with proxy environment variables up to date
+    Watch {
+        terminal: Terminal,
+
+        /// File to keep up to date
+        #[arg(short, long)]
+        output: String,
+
+        /// Seconds between checks of the system settings
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        #[clap(flatten)]
+        query: Box<QueryOptions>,
+
+        #[clap(flatten)]
+        r#override: Box<OverrideOptions>,
+
+        #[clap(flatten)]
+        env: EnvOptions,
+    },
+
+    /// Explain where every proxy variable comes from
+    Explain {
+        #[clap(flatten)]
+        query: Box<QueryOptions>,
+
+        #[clap(flatten)]
+        r#override: Box<OverrideOptions>,
+    },
+
+    /// Show which proxy applies to each URL
+    Resolve {
+        #[clap(flatten)]
+        query: Box<QueryOptions>,
+
+        #[clap(flatten)]
+        r#override: Box<OverrideOptions>,
+
+        /// URLs to resolve, e.g. https://pypi.org
+        #[arg(required = true)]
+        urls: Vec<String>,
+
+        /// Match no_proxy with the semantics of this tool; `serve` uses go's
+        #[arg(long, value_enum, default_value_t = Tool::Go)]
+        tool: Tool,
+    },
+
+    /// Check whether tools would bypass the proxies for a host
+    CheckBypass {
+        /// Host to check, optionally with a port, e.g. git.corp.example:443
+        host: String,
+
+        /// Only check the semantics of this tool [default: all of them]
+        #[arg(long, value_enum)]
+        tool: Option<Tool>,
+
+        #[clap(flatten)]
+        query: Box<QueryOptions>,
+
+        #[clap(flatten)]
+        r#override: Box<OverrideOptions>,
+    },
+
+    /// Run a local HTTP and SOCKS5 proxy that applies the no_proxy rules for every tool
+    Serve {
+        /// Port to listen on at 127.0.0.1, for both HTTP and SOCKS5
+        #[arg(long, default_value_t = LOCAL_PORT)]
+        port: u16,
+
+        /// Seconds between checks of the system settings
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        #[clap(flatten)]
+        query: Box<QueryOptions>,
+
+        #[clap(flatten)]
+        r#override: Box<OverrideOptions>,
+    },
+
+    /// Run a command with proxy environment variables set
+    Run {
+        #[clap(flatten)]
+        query: Box<QueryOptions>,
+
+        #[clap(flatten)]
+        r#override: Box<OverrideOptions>,
+
+        #[clap(flatten)]
+        env: EnvOptions,
+
+        #[clap(flatten)]
+        cache: CacheOptions,
+
+        /// Command to run, with its arguments
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -28,14 +160,40 @@ pub struct Options {
     #[arg(short, long)]
     pub output: Option<String>,
 
+    /// Emit a prompt hook that keeps the variables up to date instead
+    #[arg(long)]
+    pub hook: bool,
+
+    /// Seconds after which the hook refreshes the variables in the background
+    #[arg(long, default_value_t = 60, requires = "hook")]
+    pub hook_ttl: u64,
+
+    /// File the hook reads the variables from [default: in the cache directory]
+    #[arg(long, requires = "hook")]
+    pub hook_file: Option<String>,
+
+    /// Print the variables in a machine-readable format instead of shell code
+    #[arg(long, value_enum, conflicts_with = "hook")]
+    pub format: Option<Format>,
+
+    /// Point the variables at `termproxy serve` on this port instead [default: 3128]
+    #[arg(long, value_name = "PORT", num_args = 0..=1, default_missing_value = "3128")]
+    pub via_local: Option<u16>,
+
     #[clap(flatten)]
     pub query: QueryOptions,
 
     #[clap(flatten)]
     pub r#override: OverrideOptions,
+
+    #[clap(flatten)]
+    pub env: EnvOptions,
+
+    #[clap(flatten)]
+    pub cache: CacheOptions,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 pub struct QueryOptions {
     /// Address for query http proxy
     #[arg(long, default_value = "http://google.com")]
@@ -56,9 +214,40 @@ pub struct QueryOptions {
     /// Addresses for query no proxy, separated by comma
     #[arg(long, default_value = "localhost", value_delimiter = ',')]
     pub no_query_addrs: Vec<String>,
+
+    /// Backends to detect proxies with, tried in order until one finds a proxy;
+    /// `env` is never tried unless listed here
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = Backend::defaults())]
+    pub backend: Vec<Backend>,
 }
 
-#[derive(Parser, Debug)]
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// System settings on macOS and Windows
+    System,
+    /// Proxy resolver of the desktop portal (Linux)
+    Portal,
+    /// GNOME settings from the dconf database (Linux)
+    Gsettings,
+    /// KDE Plasma settings from kioslaverc (Linux)
+    Kde,
+    /// Proxy variables of the current environment; opt-in, as the shell
+    /// hooks would otherwise read back the variables they exported
+    Env,
+}
+
+impl Backend {
+    /// The default chain, which leaves out the opt-in `Env`.
+    pub fn defaults() -> Vec<Backend> {
+        match cfg!(target_os = "linux") {
+            true => vec![Backend::Portal, Backend::Gsettings, Backend::Kde],
+            false => vec![Backend::System],
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
 pub struct OverrideOptions {
     /// Override http proxy fetched from system
     #[arg(long)]
@@ -87,4 +276,59 @@ pub struct OverrideOptions {
     /// Force SOCKS5H protocol for bare IP addresses on Windows
     #[arg(long)]
     pub force_socks5h: bool,
+
+    /// Where to look up credentials for proxies without any, tried in order
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub credentials: Vec<CredentialSource>,
+
+    /// netrc file to read credentials from [default: $NETRC or ~/.netrc]
+    #[arg(long)]
+    pub netrc_file: Option<String>,
+}
+
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CredentialSource {
+    /// freedesktop Secret Service, e.g. GNOME Keyring or KWallet (Linux)
+    SecretService,
+    /// `machine` entries of a netrc file
+    Netrc,
+    /// Command in $TERMPROXY_CREDENTIAL_HELPER
+    Helper,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct EnvOptions {
+    /// Case of the emitted variable names
+    #[arg(long, value_enum, default_value_t = Case::Both)]
+    pub case: Case,
+
+    /// Never emit HTTP_PROXY, which CGI programs may take from the `Proxy` request header
+    #[arg(long)]
+    pub cgi_safe: bool,
+
+    /// How to write the no_proxy list
+    #[arg(long, value_enum, default_value_t = Dialect::Curl)]
+    pub no_proxy_dialect: Dialect,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct CacheOptions {
+    /// Seconds to reuse the detected proxies for, 0 disables the cache
+    #[arg(long, default_value_t = 30)]
+    pub cache_ttl: u64,
+
+    /// Detect the proxies again, ignoring the cache
+    #[arg(long)]
+    pub refresh: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    /// Only lower-case names, e.g. `http_proxy`
+    Lower,
+    /// Only upper-case names, e.g. `HTTP_PROXY`
+    Upper,
+    /// Both lower- and upper-case names
+    Both,
 }
@@ -0,0 +1,364 @@
+//! Persistent configuration file holding defaults for the query and override
+//! options, plus named profiles selected with `--profile`.
+//!
+//! ```toml
+//! no_query_addrs = ["localhost", "127.0.0.1"]
+//! backend = ["gsettings", "env"]
+//!
+//! [profiles.office]
+//! http_proxy = "http://proxy.corp.example:8080"
+//! no_proxy = "localhost,.corp.example"
+//!
+//! [[rules]]
+//! profile = "office"
+//! dns_search = "corp.example"
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use clap::{parser::ValueSource, ArgMatches};
+use serde::Deserialize;
+
+use crate::{
+    cli::{Backend, CredentialSource, OverrideOptions, QueryOptions},
+    error::Error,
+    network::{self, NetworkInfo, Rule},
+};
+
+#[derive(Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub http_query_addr: Option<String>,
+    pub https_query_addr: Option<String>,
+    pub ftp_query_addr: Option<String>,
+    pub all_query_addr: Option<String>,
+    pub no_query_addrs: Option<Vec<String>>,
+    pub backend: Option<Vec<Backend>>,
+
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    pub ftp_proxy: Option<String>,
+    pub all_proxy: Option<String>,
+    pub no_proxy: Option<String>,
+    pub no_detect: Option<bool>,
+    pub force_socks5h: Option<bool>,
+    pub credentials: Option<Vec<CredentialSource>>,
+    pub netrc_file: Option<String>,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct Config {
+    pub defaults: Settings,
+    pub profiles: BTreeMap<String, Settings>,
+    /// Network rules selecting a profile when none is given explicitly
+    pub rules: Vec<Rule>,
+}
+
+/// `$XDG_CONFIG_HOME/termproxy/config.toml`, falling back to `~/.config` (or
+/// `%APPDATA%` on Windows).
+pub fn default_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|it| !it.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            if cfg!(windows) {
+                std::env::var_os("APPDATA").map(PathBuf::from)
+            } else {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+            }
+        })?;
+    Some(base.join("termproxy").join("config.toml"))
+}
+
+/// `$XDG_CACHE_HOME/termproxy`, falling back to `~/.cache` (or
+/// `%LOCALAPPDATA%` on Windows).
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|it| !it.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            if cfg!(windows) {
+                std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+            } else {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache"))
+            }
+        })?;
+    Some(base.join("termproxy"))
+}
+
+/// Load the configuration from `path`, or from the default location if none
+/// is given. A missing default file yields an empty configuration.
+pub fn load(path: Option<&str>) -> Result<Config, Error> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => match default_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| Error::Io(format!("Failed to read {}: {}", path.display(), e)))?;
+    parse(&content).map_err(|e| Error::Parse(format!("Invalid config {}: {}", path.display(), e)))
+}
+
+pub fn parse(content: &str) -> Result<Config, String> {
+    let mut table = content.parse::<toml::Table>().map_err(|e| e.to_string())?;
+    let profiles = match table.remove("profiles") {
+        Some(profiles) => profiles.try_into().map_err(|e| e.to_string())?,
+        None => BTreeMap::new(),
+    };
+    let rules = match table.remove("rules") {
+        Some(rules) => rules.try_into().map_err(|e| e.to_string())?,
+        None => Vec::new(),
+    };
+    let defaults = toml::Value::Table(table)
+        .try_into()
+        .map_err(|e| e.to_string())?;
+    Ok(Config {
+        defaults,
+        profiles,
+        rules,
+    })
+}
+
+impl Config {
+    /// The defaults with the given profile layered on top. Without an explicit
+    /// profile, the first rule matching the active network picks one.
+    pub fn select(
+        self,
+        profile: Option<&str>,
+        network: impl FnOnce() -> NetworkInfo,
+    ) -> Result<Settings, Error> {
+        let matched;
+        let name = match profile {
+            Some(name) => name,
+            None if self.rules.is_empty() => return Ok(self.defaults),
+            None => {
+                matched = network::match_profile(&self.rules, &network()).map(str::to_string);
+                match &matched {
+                    Some(name) => name,
+                    None => return Ok(self.defaults),
+                }
+            }
+        };
+        match self.profiles.get(name) {
+            Some(profile) => Ok(self.defaults.merge(profile.clone())),
+            None => Err(Error::InvalidOverride(format!(
+                "Unknown profile `{}`, available profiles are: {}",
+                name,
+                self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            ))),
+        }
+    }
+}
+
+impl Settings {
+    /// Values set in `other` take precedence.
+    fn merge(self, other: Settings) -> Settings {
+        Settings {
+            http_query_addr: other.http_query_addr.or(self.http_query_addr),
+            https_query_addr: other.https_query_addr.or(self.https_query_addr),
+            ftp_query_addr: other.ftp_query_addr.or(self.ftp_query_addr),
+            all_query_addr: other.all_query_addr.or(self.all_query_addr),
+            no_query_addrs: other.no_query_addrs.or(self.no_query_addrs),
+            backend: other.backend.or(self.backend),
+            http_proxy: other.http_proxy.or(self.http_proxy),
+            https_proxy: other.https_proxy.or(self.https_proxy),
+            ftp_proxy: other.ftp_proxy.or(self.ftp_proxy),
+            all_proxy: other.all_proxy.or(self.all_proxy),
+            no_proxy: other.no_proxy.or(self.no_proxy),
+            no_detect: other.no_detect.or(self.no_detect),
+            force_socks5h: other.force_socks5h.or(self.force_socks5h),
+            credentials: other.credentials.or(self.credentials),
+            netrc_file: other.netrc_file.or(self.netrc_file),
+        }
+    }
+
+    /// Fill in every option that was not given on the command line.
+    pub fn apply(
+        self,
+        matches: &ArgMatches,
+        query: &mut QueryOptions,
+        r#override: &mut OverrideOptions,
+    ) {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        let fill = |id: &str, target: &mut String, value: Option<String>| {
+            if let (false, Some(value)) = (from_cli(id), value) {
+                *target = value;
+            }
+        };
+
+        fill(
+            "http_query_addr",
+            &mut query.http_query_addr,
+            self.http_query_addr,
+        );
+        fill(
+            "https_query_addr",
+            &mut query.https_query_addr,
+            self.https_query_addr,
+        );
+        fill(
+            "ftp_query_addr",
+            &mut query.ftp_query_addr,
+            self.ftp_query_addr,
+        );
+        fill(
+            "all_query_addr",
+            &mut query.all_query_addr,
+            self.all_query_addr,
+        );
+        if let (false, Some(value)) = (from_cli("no_query_addrs"), self.no_query_addrs) {
+            query.no_query_addrs = value;
+        }
+        if let (false, Some(value)) = (from_cli("backend"), self.backend) {
+            query.backend = value;
+        }
+
+        r#override.http_proxy = r#override.http_proxy.take().or(self.http_proxy);
+        r#override.https_proxy = r#override.https_proxy.take().or(self.https_proxy);
+        r#override.ftp_proxy = r#override.ftp_proxy.take().or(self.ftp_proxy);
+        r#override.all_proxy = r#override.all_proxy.take().or(self.all_proxy);
+        r#override.no_proxy = r#override.no_proxy.take().or(self.no_proxy);
+        r#override.no_detect |= self.no_detect.unwrap_or_default();
+        r#override.force_socks5h |= self.force_socks5h.unwrap_or_default();
+        if let (false, Some(value)) = (from_cli("credentials"), self.credentials) {
+            r#override.credentials = value;
+        }
+        r#override.netrc_file = r#override.netrc_file.take().or(self.netrc_file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+    use crate::cli::{Args, Commands};
+
+    const CONFIG: &str = r#"
+        http_query_addr = "http://example.com"
+        no_query_addrs = ["localhost", "127.0.0.1"]
+        no_proxy = "localhost"
+
+        [profiles.office]
+        http_proxy = "http://proxy.corp:8080"
+        no_proxy = "localhost,.corp"
+
+        [profiles.home]
+        no_detect = true
+    "#;
+
+    fn no_network() -> NetworkInfo {
+        unreachable!("network rules are not consulted")
+    }
+
+    #[test]
+    fn test_select_by_rules() {
+        let config = format!(
+            "{CONFIG}{}",
+            r#"
+            [[rules]]
+            profile = "office"
+            dns_search = "corp"
+
+            [[rules]]
+            profile = "home"
+            "#
+        );
+        let corp = || NetworkInfo {
+            search_domains: vec!["corp".to_string()],
+            ..Default::default()
+        };
+
+        let settings = parse(&config).unwrap().select(None, corp).unwrap();
+        assert_eq!(
+            settings.http_proxy.as_deref(),
+            Some("http://proxy.corp:8080")
+        );
+        let settings = parse(&config)
+            .unwrap()
+            .select(None, NetworkInfo::default)
+            .unwrap();
+        assert_eq!(settings.no_detect, Some(true));
+        let settings = parse(&config)
+            .unwrap()
+            .select(Some("home"), no_network)
+            .unwrap();
+        assert_eq!(settings.http_proxy, None);
+
+        let broken = "[[rules]]\nprofile = \"missing\"\n";
+        assert!(parse(broken)
+            .unwrap()
+            .select(None, NetworkInfo::default)
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_and_select() {
+        let config = parse(CONFIG).unwrap();
+        assert_eq!(config.profiles.len(), 2);
+
+        let settings = parse(CONFIG).unwrap().select(None, no_network).unwrap();
+        assert_eq!(settings.http_proxy, None);
+        assert_eq!(settings.no_proxy.as_deref(), Some("localhost"));
+
+        let settings = parse(CONFIG)
+            .unwrap()
+            .select(Some("office"), no_network)
+            .unwrap();
+        assert_eq!(
+            settings.http_proxy.as_deref(),
+            Some("http://proxy.corp:8080")
+        );
+        assert_eq!(settings.no_proxy.as_deref(), Some("localhost,.corp"));
+        assert_eq!(
+            settings.http_query_addr.as_deref(),
+            Some("http://example.com")
+        );
+
+        assert!(parse(CONFIG)
+            .unwrap()
+            .select(Some("vpn"), no_network)
+            .is_err());
+        assert!(parse("unknown_key = 1").is_err());
+
+        let config = parse("credentials = [\"secret-service\", \"netrc\"]").unwrap();
+        assert_eq!(
+            config.defaults.credentials,
+            Some(vec![
+                CredentialSource::SecretService,
+                CredentialSource::Netrc
+            ])
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let argv = ["termproxy", "init", "bash", "--no-proxy", "cli.example"];
+        let matches = Args::command().get_matches_from(argv);
+        let Commands::Init { options, .. } = Args::from_arg_matches(&matches).unwrap().command
+        else {
+            unreachable!()
+        };
+        let mut options = *options;
+        let sub_matches = matches.subcommand().unwrap().1;
+
+        let settings = parse(CONFIG)
+            .unwrap()
+            .select(Some("office"), no_network)
+            .unwrap();
+        settings.apply(sub_matches, &mut options.query, &mut options.r#override);
+
+        assert_eq!(options.query.http_query_addr, "http://example.com");
+        assert_eq!(options.query.https_query_addr, "https://google.com");
+        assert_eq!(options.query.no_query_addrs, ["localhost", "127.0.0.1"]);
+        assert_eq!(
+            options.r#override.http_proxy.as_deref(),
+            Some("http://proxy.corp:8080")
+        );
+        assert_eq!(options.r#override.no_proxy.as_deref(), Some("cli.example"));
+        assert!(!options.r#override.no_detect);
+    }
+}
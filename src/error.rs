@@ -0,0 +1,40 @@
+use std::fmt;
+
+/// Failures that stop a command, each with its own exit code so scripts can
+/// tell them apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Every backend failed, so nothing is known about the system proxies
+    BackendUnavailable(String),
+    /// A configuration file could not be parsed
+    Parse(String),
+    /// Reading or writing a file failed
+    Io(String),
+    /// A proxy or option given by the user is not valid
+    InvalidOverride(String),
+    /// The requested feature is not available for this terminal or platform
+    Unsupported(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidOverride(_) => 2,
+            Error::BackendUnavailable(_) => 3,
+            Error::Parse(_) => 4,
+            Error::Io(_) => 5,
+            Error::Unsupported(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BackendUnavailable(e) => write!(f, "No proxy backend available: {e}"),
+            Error::Parse(e) | Error::Io(e) | Error::InvalidOverride(e) | Error::Unsupported(e) => {
+                f.write_str(e)
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Prompt hooks that keep the proxy variables of running shells up to date.
+//!
+//! Every prompt, the hook reads a result file and re-applies it only when its
+//! content changed. When the shell's copy is older than the TTL, `termproxy
+//! init` is started in the background to rewrite the file.
+
+use crate::{error::Error, utils::Terminal};
+
+const BASH_HOOK: &str = r#"__termproxy_file=@FILE@
+__termproxy_content=
+__termproxy_last=$SECONDS
+__termproxy_hook() {
+    if (( SECONDS - __termproxy_last >= @TTL@ )); then
+        __termproxy_last=$SECONDS
+        (@COMMAND@ >/dev/null 2>&1 &)
+    fi
+    local content=
+    [[ -r "$__termproxy_file" ]] && IFS= read -r -d '' content <"$__termproxy_file"
+    if [[ "$content" != "$__termproxy_content" ]]; then
+        __termproxy_content=$content
+        @UNSET@
+        eval "$content"
+    fi
+}
+if [[ ";${PROMPT_COMMAND:-};" != *";__termproxy_hook;"* ]]; then
+    PROMPT_COMMAND="__termproxy_hook${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
+__termproxy_hook
+"#;
+
+const ZSH_HOOK: &str = r#"typeset -g __termproxy_file=@FILE@
+typeset -g __termproxy_content=
+typeset -gi __termproxy_last=$SECONDS
+__termproxy_hook() {
+    if (( SECONDS - __termproxy_last >= @TTL@ )); then
+        __termproxy_last=$SECONDS
+        (@COMMAND@ >/dev/null 2>&1 &)
+    fi
+    local content=
+    [[ -r $__termproxy_file ]] && content=$(<$__termproxy_file)
+    if [[ $content != $__termproxy_content ]]; then
+        __termproxy_content=$content
+        @UNSET@
+        eval "$content"
+    fi
+}
+autoload -Uz add-zsh-hook
+add-zsh-hook precmd __termproxy_hook
+__termproxy_hook
+"#;
+
+const FISH_HOOK: &str = r#"set -g __termproxy_file @FILE@
+set -g __termproxy_content ''
+function __termproxy_hook --on-event fish_prompt
+    set -l age (path mtime --relative $__termproxy_file 2>/dev/null)
+    if test -z "$age"; or test $age -ge @TTL@
+        @COMMAND@ >/dev/null 2>&1 &
+        disown
+    end
+    set -l content ''
+    if test -r $__termproxy_file
+        set content (string collect <$__termproxy_file)
+    end
+    if test "$content" != "$__termproxy_content"
+        set -g __termproxy_content $content
+        @UNSET@
+        printf '%s\n' $content | source
+    end
+end
+__termproxy_hook
+"#;
+
+const POWERSHELL_HOOK: &str = r#"$global:__termproxy_file = @FILE@
+$global:__termproxy_content = $null
+$global:__termproxy_last = [DateTime]::Now
+if (-not $global:__termproxy_prompt) { $global:__termproxy_prompt = $function:prompt }
+function global:__termproxy_hook {
+    if (([DateTime]::Now - $global:__termproxy_last).TotalSeconds -ge @TTL@) {
+        $global:__termproxy_last = [DateTime]::Now
+        # Start-Process joins the arguments with spaces, so quote each one
+        $arguments = @(@ARGS@) | ForEach-Object { '"' + ($_ -replace '(\\*)"', '$1$1\"' -replace '(\\+)$', '$1$1') + '"' }
+        $options = @{ FilePath = @PROGRAM@; ArgumentList = $arguments }
+        if ($PSVersionTable.Platform -ne 'Unix') { $options.WindowStyle = 'Hidden' }
+        Start-Process @options
+    }
+    $content = if (Test-Path $global:__termproxy_file) { [IO.File]::ReadAllText($global:__termproxy_file) } else { '' }
+    if ($content -ne $global:__termproxy_content) {
+        $global:__termproxy_content = $content
+        @UNSET@
+        if ($content) { Invoke-Expression $content }
+    }
+}
+function global:prompt {
+    __termproxy_hook
+    & $global:__termproxy_prompt
+}
+__termproxy_hook
+"#;
+
+const NU_HOOK: &str = r#"$env.__termproxy_last = (date now)
+$env.config = ($env.config | upsert hooks.pre_prompt (($env.config.hooks.pre_prompt? | default []) | append {||
+    if ((date now) - $env.__termproxy_last) >= @TTL@sec {
+        $env.__termproxy_last = (date now)
+        job spawn { @COMMAND@ o+e>| ignore }
+    }
+    let content = if (@FILE@ | path exists) { open --raw @FILE@ } else { '' }
+    if $content != ($env.__termproxy_content? | default '') {
+        $env.__termproxy_content = $content
+        @UNSET@
+        $content | lines | parse '$nu.env["{key}"] = "{value}"' | reduce -f {} {|it, acc| $acc | upsert $it.key $it.value } | load-env
+    }
+}))
+"#;
+
+/// Quote `value` as a single literal argument for `terminal`.
+fn quote(terminal: Terminal, value: &str) -> String {
+    match terminal {
+        Terminal::Fish => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'")),
+        Terminal::PowerShell => format!("'{}'", value.replace('\'', "''")),
+        Terminal::Nu => format!("r#'{value}'#"),
+        _ => format!("'{}'", value.replace('\'', "'\\''")),
+    }
+}
+
+/// The command line refreshing `file`: the current invocation without the
+/// hook and output options, bypassing the cache and writing to `file` instead.
+pub fn refresh_command(args: impl IntoIterator<Item = String>, file: &str) -> Vec<String> {
+    const WITH_VALUE: [&str; 4] = ["--hook-ttl", "--hook-file", "--output", "-o"];
+
+    let mut command = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let inline_value = WITH_VALUE
+            .iter()
+            .any(|it| arg.starts_with(&format!("{it}=")))
+            || (arg.starts_with("-o") && !arg.starts_with("--"));
+        if WITH_VALUE.contains(&arg.as_str()) {
+            args.next();
+        } else if arg != "--hook" && !inline_value {
+            command.push(arg);
+        }
+    }
+    if !command.iter().any(|it| it == "--refresh") {
+        command.push("--refresh".to_string());
+    }
+    command.extend(["--output".to_string(), file.to_string()]);
+    command
+}
+
+/// Shell code registering a prompt hook for `terminal` that applies `file`
+/// whenever its content changes and runs `command` to refresh it once the
+/// applied content is older than `ttl` seconds. `unset` clears the variables
+/// before a new set is applied.
+pub fn hook_str(
+    terminal: Terminal,
+    file: &str,
+    ttl: u64,
+    command: &[String],
+    unset: &str,
+) -> Result<String, Error> {
+    let template = match terminal {
+        Terminal::Bash => BASH_HOOK,
+        Terminal::Zsh => ZSH_HOOK,
+        Terminal::Fish => FISH_HOOK,
+        Terminal::PowerShell => POWERSHELL_HOOK,
+        Terminal::Nu => NU_HOOK,
+        _ => {
+            return Err(Error::Unsupported(format!(
+                "Prompt hooks are not supported for {terminal:?}, use bash, fish, nu, powershell or zsh"
+            )))
+        }
+    };
+
+    let quoted: Vec<String> = command.iter().map(|it| quote(terminal, it)).collect();
+    let (program, args) = quoted
+        .split_first()
+        .ok_or_else(|| Error::Unsupported("Missing refresh command".to_string()))?;
+    let command = match terminal {
+        Terminal::Nu => format!("run-external {}", quoted.join(" ")),
+        _ => quoted.join(" "),
+    };
+    Ok(template
+        .replace("@FILE@", &quote(terminal, file))
+        .replace("@TTL@", &ttl.to_string())
+        .replace("@COMMAND@", &command)
+        .replace("@PROGRAM@", program)
+        .replace("@ARGS@", &args.join(", "))
+        .replace("@UNSET@", &unset.replace('\n', "\n        ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|it| it.to_string()).collect()
+    }
+
+    #[test]
+    fn test_refresh_command() {
+        let argv = args(&[
+            "termproxy",
+            "init",
+            "bash",
+            "--hook",
+            "--hook-ttl",
+            "30",
+            "-o",
+            "out.sh",
+            "--hook-file=/tmp/f",
+            "--no-proxy",
+            "localhost",
+            "-oout.sh",
+        ]);
+        assert_eq!(
+            refresh_command(argv, "/tmp/f"),
+            args(&[
+                "termproxy",
+                "init",
+                "bash",
+                "--no-proxy",
+                "localhost",
+                "--refresh",
+                "--output",
+                "/tmp/f"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_hook_str() {
+        let command = args(&[
+            "/usr/bin/termproxy",
+            "init",
+            "bash",
+            "--output",
+            "/tmp/it's here",
+        ]);
+        let hook = hook_str(Terminal::Bash, "/tmp/f", 60, &command, "unset a\nunset b").unwrap();
+        assert!(hook.contains("__termproxy_file='/tmp/f'\n"));
+        assert!(hook.contains("SECONDS - __termproxy_last >= 60"));
+        assert!(hook.contains(
+            "('/usr/bin/termproxy' 'init' 'bash' '--output' '/tmp/it'\\''s here' >/dev/null"
+        ));
+        assert!(hook.contains("        unset a\n        unset b\n"));
+
+        let hook = hook_str(Terminal::PowerShell, "/tmp/f", 60, &command, "").unwrap();
+        assert!(hook.contains(
+            "@('init', 'bash', '--output', '/tmp/it''s here') | ForEach-Object { '\"' + "
+        ));
+        assert!(hook.contains("FilePath = '/usr/bin/termproxy'; ArgumentList = $arguments"));
+
+        assert!(hook_str(Terminal::Cmd, "/tmp/f", 60, &command, "").is_err());
+    }
+}
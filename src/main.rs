@@ -1,22 +1,179 @@
-use clap::Parser;
+use std::io::Write;
+
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches};
+use colored::Colorize;
+
+use error::Error;
 
 mod cli;
+mod config;
+mod error;
+mod hook;
+mod network;
+mod no_proxy;
+mod pac;
+mod proxy_url;
+mod serve;
 mod utils;
 mod work;
 
+/// Load the configuration file and select the requested profile.
+fn load_settings(path: Option<&str>, profile: Option<&str>) -> Result<config::Settings, Error> {
+    config::load(path).and_then(|config| config.select(profile, work::network_info))
+}
+
+/// Write `content` to `file`, or to stdout at once, so a failure never leaves
+/// partial shell code behind.
+fn write_output(file: Option<&str>, content: &str) -> Result<(), Error> {
+    match file {
+        Some(file) => work::write_atomic(file, content),
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout
+                .write_all(content.as_bytes())
+                .and_then(|_| stdout.flush())
+                .map_err(|e| Error::Io(format!("Failed to write to stdout: {e}")))
+        }
+    }
+}
+
 fn main() {
-    let arg = cli::Args::parse();
-    match arg.command {
-        cli::Commands::Init { terminal, options } => {
-            let result = work::init(terminal, options.query, options.r#override);
-            match options.output {
-                Some(file) => std::fs::write(file, result).expect("Failed to write to file"),
-                None => print!("{result}"),
+    let matches = cli::Args::command().get_matches();
+    let cli::Args {
+        command,
+        config,
+        profile,
+        show_secrets,
+    } = cli::Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    utils::set_show_secrets(show_secrets);
+    let sub_matches = matches.subcommand().map_or(&matches, |(_, it)| it);
+    let settings = || load_settings(config.as_deref(), profile.as_deref());
+
+    if let Err(e) = execute(command, sub_matches, settings) {
+        eprintln!("{}: {}", "Error".red(), utils::redact(&e.to_string()));
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn execute(
+    command: cli::Commands,
+    sub_matches: &ArgMatches,
+    settings: impl Fn() -> Result<config::Settings, Error>,
+) -> Result<(), Error> {
+    match command {
+        cli::Commands::Init {
+            terminal,
+            mut options,
+        } => {
+            settings()?.apply(sub_matches, &mut options.query, &mut options.r#override);
+            if let Some(port) = options.via_local {
+                work::use_local_proxy(&mut options.r#override, port);
             }
+            let result = match (options.hook, options.format) {
+                (_, Some(format)) => work::export(
+                    format,
+                    options.query,
+                    options.r#override,
+                    options.env,
+                    &options.cache,
+                )?,
+                (true, None) => {
+                    let file = match options.hook_file {
+                        Some(file) => file,
+                        None => {
+                            let name = format!("hook.{terminal:?}").to_lowercase();
+                            let dir = config::cache_dir().ok_or_else(|| {
+                                Error::Io("No cache directory, use --hook-file".to_string())
+                            })?;
+                            dir.join(name).to_string_lossy().into_owned()
+                        }
+                    };
+                    work::hook(
+                        terminal,
+                        options.query,
+                        options.r#override,
+                        options.env,
+                        &options.cache,
+                        &file,
+                        options.hook_ttl,
+                    )?
+                }
+                (false, None) => work::init(
+                    terminal,
+                    options.query,
+                    options.r#override,
+                    options.env,
+                    &options.cache,
+                )?,
+            };
+            write_output(options.output.as_deref(), &result)
+        }
+        cli::Commands::Cat { format } => write_output(None, &work::cat(format)),
+        cli::Commands::Off { terminal, output } => {
+            write_output(output.as_deref(), &work::off(terminal))
+        }
+        cli::Commands::Watch {
+            terminal,
+            output,
+            interval,
+            mut query,
+            mut r#override,
+            env,
+        } => {
+            settings()?.apply(sub_matches, &mut query, &mut r#override);
+            let interval = std::time::Duration::from_secs(interval);
+            work::watch(terminal, *query, *r#override, env, &output, interval)
+        }
+        cli::Commands::Explain {
+            mut query,
+            mut r#override,
+        } => {
+            settings()?.apply(sub_matches, &mut query, &mut r#override);
+            let from_cli =
+                |id: &str| sub_matches.value_source(id) == Some(ValueSource::CommandLine);
+            let result = work::explain(*query, *r#override, from_cli)?;
+            write_output(None, &result)
+        }
+        cli::Commands::Resolve {
+            mut query,
+            mut r#override,
+            urls,
+            tool,
+        } => {
+            settings()?.apply(sub_matches, &mut query, &mut r#override);
+            write_output(None, &work::resolve(*query, *r#override, &urls, tool)?)
+        }
+        cli::Commands::CheckBypass {
+            host,
+            tool,
+            mut query,
+            mut r#override,
+        } => {
+            settings()?.apply(sub_matches, &mut query, &mut r#override);
+            let tools = tool.map_or(no_proxy::Tool::ALL.to_vec(), |it| vec![it]);
+            let result = work::check_bypass(*query, *r#override, &host, &tools)?;
+            write_output(None, &result)
+        }
+        cli::Commands::Serve {
+            port,
+            interval,
+            mut query,
+            mut r#override,
+        } => {
+            settings()?.apply(sub_matches, &mut query, &mut r#override);
+            let interval = std::time::Duration::from_secs(interval);
+            work::serve(*query, *r#override, port, interval)
         }
-        cli::Commands::Cat => {
-            let result = work::cat();
-            print!("{result}");
+        cli::Commands::Run {
+            mut query,
+            mut r#override,
+            env,
+            cache,
+            command,
+        } => {
+            settings()?.apply(sub_matches, &mut query, &mut r#override);
+            let code = work::run(*query, *r#override, env, &cache, command)?;
+            std::process::exit(code);
         }
-    };
+    }
 }
@@ -0,0 +1,134 @@
+//! Facts about the active network and the configuration rules matching them,
+//! used to pick a profile without manual switching.
+//!
+//! ```toml
+//! [[rules]]
+//! profile = "office"
+//! ssid = "CorpWiFi"
+//!
+//! [[rules]]
+//! profile = "vpn"
+//! interface = "tun"
+//! ```
+
+use std::net::IpAddr;
+
+use serde::Deserialize;
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct NetworkInfo {
+    /// Default gateways
+    pub gateways: Vec<IpAddr>,
+    /// DNS search domains
+    pub search_domains: Vec<String>,
+    /// SSIDs of the connected Wi-Fi networks
+    pub ssids: Vec<String>,
+    /// Names of the network interfaces that are up
+    pub interfaces: Vec<String>,
+}
+
+/// Selects `profile` when every condition that is set matches. A rule without
+/// conditions always matches, which makes it a fallback.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub profile: String,
+    /// Default gateway address
+    pub gateway: Option<IpAddr>,
+    /// DNS search domain, e.g. `corp.example`
+    pub dns_search: Option<String>,
+    /// Wi-Fi network name
+    pub ssid: Option<String>,
+    /// Interface name prefix, e.g. `tun`, `wg` or `utun` for a VPN
+    pub interface: Option<String>,
+}
+
+impl Rule {
+    pub fn matches(&self, info: &NetworkInfo) -> bool {
+        let gateway = self
+            .gateway
+            .is_none_or(|gateway| info.gateways.contains(&gateway));
+        let dns_search = self.dns_search.as_ref().is_none_or(|domain| {
+            let domain = domain.trim_matches('.');
+            info.search_domains
+                .iter()
+                .any(|it| it.trim_matches('.').eq_ignore_ascii_case(domain))
+        });
+        let ssid = self
+            .ssid
+            .as_ref()
+            .is_none_or(|ssid| info.ssids.contains(ssid));
+        let interface = self
+            .interface
+            .as_ref()
+            .is_none_or(|prefix| info.interfaces.iter().any(|it| it.starts_with(prefix)));
+        gateway && dns_search && ssid && interface
+    }
+}
+
+/// The profile of the first rule matching `info`.
+pub fn match_profile<'a>(rules: &'a [Rule], info: &NetworkInfo) -> Option<&'a str> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(info))
+        .map(|rule| rule.profile.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn rule(profile: &str) -> Rule {
+        Rule {
+            profile: profile.to_string(),
+            gateway: None,
+            dns_search: None,
+            ssid: None,
+            interface: None,
+        }
+    }
+
+    fn office() -> NetworkInfo {
+        NetworkInfo {
+            gateways: vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))],
+            search_domains: vec!["corp.example".to_string()],
+            ssids: vec!["CorpWiFi".to_string()],
+            interfaces: vec!["lo".to_string(), "wlan0".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_match_profile() {
+        let rules = vec![
+            Rule {
+                interface: Some("tun".to_string()),
+                ..rule("vpn")
+            },
+            Rule {
+                ssid: Some("CorpWiFi".to_string()),
+                gateway: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
+                ..rule("office")
+            },
+            Rule {
+                dns_search: Some("corp.example.".to_string()),
+                ..rule("wired")
+            },
+            rule("home"),
+        ];
+
+        assert_eq!(match_profile(&rules, &office()), Some("office"));
+
+        let mut vpn = office();
+        vpn.interfaces.push("tun0".to_string());
+        assert_eq!(match_profile(&rules, &vpn), Some("vpn"));
+
+        let mut wired = office();
+        wired.ssids.clear();
+        assert_eq!(match_profile(&rules, &wired), Some("wired"));
+
+        assert_eq!(match_profile(&rules, &NetworkInfo::default()), Some("home"));
+        assert_eq!(match_profile(&rules[..3], &NetworkInfo::default()), None);
+    }
+}
@@ -0,0 +1,520 @@
+//! Parsing `no_proxy` lists and matching hosts against them.
+//!
+//! There is no standard for `no_proxy`, and common tools disagree on leading
+//! dots, wildcards, CIDRs, ports and IPv6, so matching takes the [`Tool`] whose
+//! semantics to follow.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+
+use clap::ValueEnum;
+
+/// A consumer of the `no_proxy` variable.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    /// curl 7.86 or later: domain suffixes, CIDRs, no ports or wildcards
+    Curl,
+    /// Go's `net/http`: `*.` and `.` match subdomains only, ports and CIDRs
+    Go,
+    /// Python `requests`: plain string suffixes, IPv4 CIDRs
+    Python,
+    /// GNU Wget: domain suffixes only
+    Wget,
+    /// Node's `proxy-from-env`: exact hosts unless prefixed by `.` or `*`, ports
+    Node,
+}
+
+impl Tool {
+    pub const ALL: [Tool; 5] = [Tool::Curl, Tool::Go, Tool::Python, Tool::Wget, Tool::Node];
+}
+
+impl fmt::Display for Tool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format!("{self:?}").to_lowercase())
+    }
+}
+
+/// How a domain entry starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prefix {
+    /// `example.com`
+    None,
+    /// `.example.com`
+    Dot,
+    /// `*.example.com`
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    /// `*`
+    Any,
+    Ip(IpAddr),
+    Cidr(IpAddr, u32),
+    /// A lower-case domain name without its prefix
+    Domain(String, Prefix),
+}
+
+/// One entry of a `no_proxy` list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule<'a> {
+    /// The entry as written
+    pub entry: &'a str,
+    pub host: Host,
+    pub port: Option<u16>,
+}
+
+/// Split `host:port`, `[v6]:port` or `[v6]`; a bare IPv6 address has no port.
+fn split_port(value: &str) -> (&str, Option<u16>) {
+    if let Some(rest) = value.strip_prefix('[') {
+        if let Some((host, after)) = rest.split_once(']') {
+            let port = after.strip_prefix(':').and_then(|it| it.parse().ok());
+            return (host, port);
+        }
+    }
+    match value.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (value, None),
+        },
+        _ => (value, None),
+    }
+}
+
+impl<'a> Rule<'a> {
+    pub fn parse(entry: &'a str) -> Rule<'a> {
+        if entry == "*" {
+            return Rule {
+                entry,
+                host: Host::Any,
+                port: None,
+            };
+        }
+        if let Some((network, prefix)) = entry.split_once('/') {
+            let network = network.trim_start_matches('[').trim_end_matches(']');
+            if let (Ok(network), Ok(prefix)) = (network.parse(), prefix.parse()) {
+                return Rule {
+                    entry,
+                    host: Host::Cidr(network, prefix),
+                    port: None,
+                };
+            }
+        }
+        let (host, port) = split_port(entry);
+        let host = match host.parse() {
+            Ok(ip) => Host::Ip(ip),
+            Err(_) => {
+                let name = host.trim_end_matches('.').to_ascii_lowercase();
+                match (name.strip_prefix("*."), name.strip_prefix('.')) {
+                    (Some(name), _) => Host::Domain(name.to_string(), Prefix::Wildcard),
+                    (None, Some(name)) => Host::Domain(name.to_string(), Prefix::Dot),
+                    (None, None) => Host::Domain(name, Prefix::None),
+                }
+            }
+        };
+        Rule { entry, host, port }
+    }
+}
+
+/// The entries of a list separated by commas or whitespace.
+pub fn parse(no_proxy: &str) -> Vec<Rule<'_>> {
+    no_proxy
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|entry| !entry.is_empty())
+        .map(Rule::parse)
+        .collect()
+}
+
+/// Whether `ip` is inside `network`/`prefix`.
+pub fn in_cidr(ip: IpAddr, network: IpAddr, prefix: u32) -> bool {
+    let (ip, network, bits) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            (u32::from(ip) as u128, u32::from(network) as u128, 32)
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (u128::from(ip), u128::from(network), 128),
+        _ => return false,
+    };
+    if prefix > bits {
+        return false;
+    }
+    let mask = match prefix {
+        0 => 0,
+        prefix => u128::MAX << (bits - prefix),
+    };
+    ip & mask == network & mask
+}
+
+/// `host` is `domain` or one of its subdomains.
+fn is_subdomain(host: &str, domain: &str, include_self: bool) -> bool {
+    (include_self && host == domain)
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|it| it.ends_with('.'))
+}
+
+/// A host being checked, with brackets and the trailing dot removed.
+struct Target<'a> {
+    host: &'a str,
+    ip: Option<IpAddr>,
+    port: Option<u16>,
+}
+
+impl Tool {
+    fn matches(&self, rule: &Rule, target: &Target) -> bool {
+        let port_matches = rule.port.is_none() || rule.port == target.port;
+        match self {
+            Tool::Curl => match &rule.host {
+                _ if rule.port.is_some() => false,
+                Host::Any => true,
+                Host::Ip(ip) => target.ip == Some(*ip),
+                Host::Cidr(network, prefix) => {
+                    target.ip.is_some_and(|ip| in_cidr(ip, *network, *prefix))
+                }
+                Host::Domain(_, Prefix::Wildcard) => false,
+                Host::Domain(name, _) => is_subdomain(target.host, name, true),
+            },
+            Tool::Go => match &rule.host {
+                _ if !port_matches => false,
+                Host::Any => true,
+                Host::Ip(ip) => target.ip == Some(*ip),
+                Host::Cidr(network, prefix) => {
+                    target.ip.is_some_and(|ip| in_cidr(ip, *network, *prefix))
+                }
+                Host::Domain(name, prefix) => {
+                    is_subdomain(target.host, name, *prefix == Prefix::None)
+                }
+            },
+            Tool::Python => {
+                if rule.host == Host::Any {
+                    return true;
+                }
+                if let (Some(IpAddr::V4(ip)), Host::Cidr(network @ IpAddr::V4(_), prefix)) =
+                    (target.ip, &rule.host)
+                {
+                    return in_cidr(IpAddr::V4(ip), *network, *prefix);
+                }
+                // requests compares plain suffixes, urllib the name without dots
+                let entry = rule.entry.to_ascii_lowercase();
+                let host_port = target.port.map(|port| format!("{}:{}", target.host, port));
+                let mut names = std::iter::once(target.host.to_string()).chain(host_port);
+                let stripped = entry.trim_start_matches('.');
+                names.any(|name| name.ends_with(&entry) || name == stripped)
+            }
+            Tool::Wget => {
+                let entry = rule.entry.to_ascii_lowercase();
+                entry.starts_with('.') && target.host.ends_with(&entry)
+                    || is_subdomain(target.host, &entry, true)
+            }
+            Tool::Node => {
+                if rule.entry == "*" {
+                    return true;
+                }
+                // `^(.+):(\d+)$`, so a bare IPv6 address loses its last group
+                let entry = rule.entry.to_ascii_lowercase();
+                let (host, port) = match entry.rsplit_once(':') {
+                    Some((host, port))
+                        if !host.is_empty()
+                            && !port.is_empty()
+                            && port.bytes().all(|it| it.is_ascii_digit()) =>
+                    {
+                        (host.to_string(), port.parse::<u16>().ok())
+                    }
+                    _ => (entry, None),
+                };
+                if port.is_some() && port != target.port {
+                    return false;
+                }
+                match host
+                    .strip_prefix('*')
+                    .or_else(|| host.starts_with('.').then_some(host.as_str()))
+                {
+                    Some(suffix) => target.host.ends_with(suffix),
+                    None => target.host == host,
+                }
+            }
+        }
+    }
+}
+
+/// Why a host bypasses the proxies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason<'a> {
+    Entry(&'a str),
+    /// Go never proxies `localhost` and loopback addresses
+    Loopback,
+}
+
+impl fmt::Display for Reason<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Entry(entry) => write!(f, "no_proxy entry {entry}"),
+            Reason::Loopback => f.write_str("loopback hosts are never proxied"),
+        }
+    }
+}
+
+/// Why `tool` would not use a proxy for `host`, if it would not.
+///
+/// `host` may be bracketed and may carry a port; entries with a port only
+/// match hosts with the same port.
+pub fn find_match<'a>(tool: Tool, no_proxy: &'a str, host: &str) -> Option<Reason<'a>> {
+    let (host, port) = split_port(host);
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let ip = host.parse::<IpAddr>().ok();
+    if tool == Tool::Go && (host == "localhost" || ip.is_some_and(|it| it.is_loopback())) {
+        return Some(Reason::Loopback);
+    }
+
+    let target = Target {
+        host: &host,
+        ip,
+        port,
+    };
+    parse(no_proxy)
+        .into_iter()
+        .find(|rule| tool.matches(rule, &target))
+        .map(|rule| Reason::Entry(rule.entry))
+}
+
+/// How to write the `no_proxy` list.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// As detected, following curl
+    Curl,
+    /// Rewritten into the forms most tools agree on
+    Portable,
+}
+
+impl Dialect {
+    pub fn rewrite<'a>(&self, no_proxy: Cow<'a, str>) -> Cow<'a, str> {
+        match self {
+            Dialect::Curl => no_proxy,
+            Dialect::Portable => Cow::Owned(portable(&no_proxy)),
+        }
+    }
+}
+
+/// Rewrite wildcard domains like `*.corp` to `.corp`, drop brackets around
+/// IPv6 addresses without a port and separate the entries by commas alone,
+/// skipping duplicates.
+pub fn portable(no_proxy: &str) -> String {
+    let mut entries: Vec<String> = Vec::new();
+    for rule in parse(no_proxy) {
+        let port = rule.port.map_or(String::new(), |port| format!(":{port}"));
+        let entry = match &rule.host {
+            Host::Domain(name, Prefix::None) => format!("{name}{port}"),
+            Host::Domain(name, Prefix::Dot | Prefix::Wildcard) => format!(".{name}{port}"),
+            Host::Ip(ip) if rule.port.is_none() => ip.to_string(),
+            _ => rule.entry.to_string(),
+        };
+        if !entries.contains(&entry) {
+            entries.push(entry);
+        }
+    }
+    entries.join(",")
+}
+
+/// An IPv6 address without brackets and zone ID, which no_proxy cannot match.
+pub fn strip_ipv6(addr: &str) -> Option<Ipv6Addr> {
+    let addr = addr
+        .strip_prefix('[')
+        .and_then(|it| it.strip_suffix(']'))
+        .unwrap_or(addr);
+    let addr = addr.split_once('%').map_or(addr, |(addr, _)| addr);
+    addr.parse().ok()
+}
+
+/// A host each entry is meant to cover, to see which tools agree.
+fn probe(rule: &Rule) -> String {
+    let with_port = |host: String| match rule.port {
+        Some(port) => format!("{host}:{port}"),
+        None => host,
+    };
+    let ip_host = |ip: &IpAddr| match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{ip}]"),
+    };
+    match &rule.host {
+        Host::Any => "example.com".to_string(),
+        Host::Ip(ip) | Host::Cidr(ip, _) => with_port(ip_host(ip)),
+        Host::Domain(name, Prefix::None) => with_port(name.clone()),
+        Host::Domain(name, _) => with_port(format!("www.{name}")),
+    }
+}
+
+/// Warnings for the entries that some tools interpret differently, e.g. CIDRs
+/// that wget ignores or `*.` wildcards that curl takes literally.
+pub fn lint(no_proxy: &str) -> Vec<String> {
+    lint_entries(no_proxy, false)
+}
+
+/// The warnings of `lint` that `--no-proxy-dialect portable` would fix, so
+/// entries nothing can be done about, like CIDRs, do not warn on every shell
+/// startup.
+pub fn lint_fixable(no_proxy: &str) -> Vec<String> {
+    lint_entries(no_proxy, true)
+}
+
+fn lint_entries(no_proxy: &str, only_fixable: bool) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for rule in parse(no_proxy) {
+        let probe = probe(&rule);
+        let ignored: Vec<String> = Tool::ALL
+            .into_iter()
+            .filter(|&tool| find_match(tool, rule.entry, &probe).is_none())
+            .map(|tool| tool.to_string())
+            .collect();
+        if ignored.is_empty() {
+            continue;
+        }
+        let mut warning = format!(
+            "no_proxy entry `{}` is ignored by {}",
+            rule.entry,
+            ignored.join(", ")
+        );
+        let rewritten = portable(rule.entry);
+        let fixable = rewritten != rule.entry && lint(&rewritten).is_empty();
+        if fixable {
+            warning.push_str(&format!(
+                ", --no-proxy-dialect portable writes `{rewritten}`"
+            ));
+        } else if only_fixable {
+            continue;
+        }
+        warnings.push(warning);
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let rules = parse("*, .corp.example,*.lan example.org:8080,10.0.0.0/8,::1,[::1]:80");
+        let hosts: Vec<_> = rules.iter().map(|it| (&it.host, it.port)).collect();
+        assert_eq!(
+            hosts,
+            vec![
+                (&Host::Any, None),
+                (&Host::Domain("corp.example".to_string(), Prefix::Dot), None),
+                (&Host::Domain("lan".to_string(), Prefix::Wildcard), None),
+                (
+                    &Host::Domain("example.org".to_string(), Prefix::None),
+                    Some(8080)
+                ),
+                (&Host::Cidr("10.0.0.0".parse().unwrap(), 8), None),
+                (&Host::Ip("::1".parse().unwrap()), None),
+                (&Host::Ip("::1".parse().unwrap()), Some(80)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_match() {
+        let no_proxy = "localhost, .corp.example,example.org,10.0.0.0/8,::1,192.168.1.1";
+        let curl = |host| find_match(Tool::Curl, no_proxy, host);
+        assert_eq!(curl("localhost"), Some(Reason::Entry("localhost")));
+        assert_eq!(
+            curl("git.corp.example"),
+            Some(Reason::Entry(".corp.example"))
+        );
+        assert_eq!(curl("corp.example"), Some(Reason::Entry(".corp.example")));
+        assert_eq!(curl("www.example.org"), Some(Reason::Entry("example.org")));
+        assert_eq!(curl("notexample.org"), None);
+        assert_eq!(curl("10.1.2.3"), Some(Reason::Entry("10.0.0.0/8")));
+        assert_eq!(curl("11.1.2.3"), None);
+        assert_eq!(curl("[::1]"), Some(Reason::Entry("::1")));
+        assert_eq!(curl("192.168.1.1:443"), Some(Reason::Entry("192.168.1.1")));
+        assert_eq!(curl("pypi.org"), None);
+
+        assert_eq!(
+            find_match(Tool::Curl, "*", "pypi.org"),
+            Some(Reason::Entry("*"))
+        );
+        assert_eq!(find_match(Tool::Curl, "", "pypi.org"), None);
+        assert_eq!(
+            find_match(Tool::Curl, "fd00::/8", "fd12::1"),
+            Some(Reason::Entry("fd00::/8"))
+        );
+        assert_eq!(find_match(Tool::Curl, "0.0.0.0/0", "::1"), None);
+    }
+
+    #[test]
+    fn test_tools() {
+        let check = |no_proxy, host| {
+            Tool::ALL
+                .into_iter()
+                .filter(|&tool| find_match(tool, no_proxy, host).is_some())
+                .collect::<Vec<_>>()
+        };
+        use Tool::*;
+
+        // Leading dots
+        assert_eq!(check(".corp.example", "corp.example"), [Curl, Python]);
+        assert_eq!(
+            check(".corp.example", "git.corp.example"),
+            [Curl, Go, Python, Wget, Node]
+        );
+        // Plain names
+        assert_eq!(
+            check("corp.example", "git.corp.example"),
+            [Curl, Go, Python, Wget]
+        );
+        assert_eq!(check("example.org", "badexample.org"), [Python]);
+        // Wildcards
+        assert_eq!(check("*.corp.example", "git.corp.example"), [Go, Node]);
+        assert_eq!(check("*", "pypi.org"), [Curl, Go, Python, Node]);
+        // CIDRs
+        assert_eq!(check("10.0.0.0/16", "10.0.1.2"), [Curl, Go, Python]);
+        assert_eq!(check("fd00::/8", "[fd00::1]"), [Curl, Go]);
+        // Ports
+        assert_eq!(
+            check("git.corp.example:8443", "git.corp.example:8443"),
+            [Go, Python, Node]
+        );
+        assert_eq!(
+            check("git.corp.example:8443", "git.corp.example:443"),
+            Vec::<Tool>::new()
+        );
+        // IPv6
+        assert_eq!(check("::1", "[::1]:8080"), [Curl, Go, Python, Wget]);
+        assert_eq!(
+            check("2001:db8::1", "[2001:db8::1]"),
+            [Curl, Go, Python, Wget]
+        );
+        // Loopback
+        assert_eq!(check("", "localhost"), [Go]);
+    }
+
+    #[test]
+    fn test_portable() {
+        assert_eq!(
+            portable("*.corp.example, .lan  localhost,[::1],[::1]:80,LAN.,.lan,10.0.0.0/8"),
+            ".corp.example,.lan,localhost,::1,[::1]:80,lan,10.0.0.0/8"
+        );
+        assert_eq!(portable(""), "");
+    }
+
+    #[test]
+    fn test_lint() {
+        assert_eq!(
+            lint("localhost,.corp.example,127.0.0.1,*.corp,10.0.0.0/16,::1,proxy:8080"),
+            vec![
+                "no_proxy entry `*.corp` is ignored by curl, python, wget, --no-proxy-dialect portable writes `.corp`",
+                "no_proxy entry `10.0.0.0/16` is ignored by wget, node",
+                "no_proxy entry `::1` is ignored by node",
+                "no_proxy entry `proxy:8080` is ignored by curl, wget",
+            ]
+        );
+        assert!(lint(&portable("*.corp.example")).is_empty());
+
+        assert_eq!(
+            lint_fixable("localhost,127.0.0.0/8,::1,*.corp"),
+            vec!["no_proxy entry `*.corp` is ignored by curl, python, wget, --no-proxy-dialect portable writes `.corp`"]
+        );
+        assert!(lint_fixable("localhost,127.0.0.0/8,::1").is_empty());
+    }
+}
@@ -0,0 +1,1748 @@
+//! A tiny JavaScript interpreter covering the subset of the language used by
+//! proxy auto-config scripts: functions, variables, `if`/`else`, `switch`,
+//! loops, string and array helpers, regular expression literals, and the
+//! usual operators.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+pub type NativeFn = Rc<dyn Fn(&[Value]) -> Value>;
+
+#[derive(Clone)]
+pub enum Value {
+    Undefined,
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Array(Rc<Array>),
+    Regex(Rc<Regex>),
+    Func(Rc<Function>),
+    Native(NativeFn),
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Func(func) => write!(f, "function {}", func.name),
+            Value::Native(_) => write!(f, "function [native]"),
+            _ => write!(f, "{}", self.to_str()),
+        }
+    }
+}
+
+impl Value {
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Undefined | Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Num(n) => *n != 0.0 && !n.is_nan(),
+            Value::Str(s) => !s.is_empty(),
+            Value::Array(_) | Value::Regex(_) | Value::Func(_) | Value::Native(_) => true,
+        }
+    }
+
+    pub fn to_str(&self) -> String {
+        match self {
+            Value::Undefined => "undefined".to_string(),
+            Value::Null => "null".to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Num(n) => {
+                if n.fract() == 0.0 && n.abs() < 1e15 {
+                    format!("{}", *n as i64)
+                } else {
+                    n.to_string()
+                }
+            }
+            Value::Str(s) => s.clone(),
+            Value::Array(array) => array.join(","),
+            Value::Regex(re) => format!("{re:?}"),
+            Value::Func(_) | Value::Native(_) => "function".to_string(),
+        }
+    }
+
+    pub fn to_num(&self) -> f64 {
+        match self {
+            Value::Undefined => f64::NAN,
+            Value::Null => 0.0,
+            Value::Bool(b) => *b as u8 as f64,
+            Value::Num(n) => *n,
+            Value::Str(s) => {
+                let s = s.trim();
+                if s.is_empty() {
+                    0.0
+                } else {
+                    s.parse().unwrap_or(f64::NAN)
+                }
+            }
+            _ => f64::NAN,
+        }
+    }
+
+    fn type_of(&self) -> &'static str {
+        match self {
+            Value::Undefined => "undefined",
+            Value::Null | Value::Array(_) | Value::Regex(_) => "object",
+            Value::Bool(_) => "boolean",
+            Value::Num(_) => "number",
+            Value::Str(_) => "string",
+            Value::Func(_) | Value::Native(_) => "function",
+        }
+    }
+
+    fn strict_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Undefined, Value::Undefined) | (Value::Null, Value::Null) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Num(a), Value::Num(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b),
+            (Value::Regex(a), Value::Regex(b)) => Rc::ptr_eq(a, b),
+            (Value::Func(a), Value::Func(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    fn loose_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Undefined | Value::Null, Value::Undefined | Value::Null) => true,
+            (Value::Undefined | Value::Null, _) | (_, Value::Undefined | Value::Null) => false,
+            (Value::Str(_), Value::Str(_)) => self.strict_eq(other),
+            (
+                Value::Num(_) | Value::Str(_) | Value::Bool(_),
+                Value::Num(_) | Value::Str(_) | Value::Bool(_),
+            ) => self.to_num() == other.to_num(),
+            _ => self.strict_eq(other),
+        }
+    }
+}
+
+/// An array, shared by reference as in JavaScript. To rule out cycles and
+/// deep nesting, an array stored in another one is marked `nested` and may
+/// not hold arrays itself.
+pub struct Array {
+    items: RefCell<Vec<Value>>,
+    nested: Cell<bool>,
+}
+
+impl Array {
+    fn from_items(items: Vec<Value>) -> Result<Value, String> {
+        if items.len() > MAX_ARRAY_LEN {
+            return Err("Array too large".to_string());
+        }
+        let array = Array {
+            items: RefCell::new(Vec::new()),
+            nested: Cell::new(false),
+        };
+        for item in &items {
+            array.check_item(item)?;
+        }
+        array.items.replace(items);
+        Ok(Value::Array(Rc::new(array)))
+    }
+
+    /// Check that `value` may be stored in this array and mark it as nested.
+    fn check_item(&self, value: &Value) -> Result<(), String> {
+        if let Value::Array(inner) = value {
+            let has_arrays = || {
+                inner
+                    .items
+                    .borrow()
+                    .iter()
+                    .any(|it| matches!(it, Value::Array(_)))
+            };
+            if self.nested.get() || std::ptr::eq(self, &**inner) || has_arrays() {
+                return Err("Arrays can only be nested two levels deep".to_string());
+            }
+            inner.nested.set(true);
+        }
+        Ok(())
+    }
+
+    /// The items joined by `sep`, stopping early once longer than `MAX_STRING_LEN`.
+    fn join(&self, sep: &str) -> String {
+        let mut res = String::new();
+        for (i, item) in self.items.borrow().iter().enumerate() {
+            if res.len() > MAX_STRING_LEN {
+                break;
+            }
+            if i > 0 {
+                res.push_str(sep);
+            }
+            res.push_str(&item.to_str());
+        }
+        res
+    }
+}
+
+/// A regular expression literal like `/^10\./i`, matched with `regex_lite`,
+/// which lacks look-around and backreferences. The `g` flag only makes
+/// `match` and `replace` cover every match.
+pub struct Regex {
+    source: String,
+    flags: String,
+    re: regex_lite::Regex,
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}/{}", self.source, self.flags)
+    }
+}
+
+impl Regex {
+    fn new(source: &str, flags: &str) -> Result<Self, String> {
+        let mut inline = String::new();
+        for flag in flags.chars() {
+            match flag {
+                'i' | 'm' | 's' => inline.push(flag),
+                'g' => {}
+                _ => return Err(format!("Unsupported regular expression flag: {flag}")),
+            }
+        }
+        let pattern = match inline.is_empty() {
+            true => source.to_string(),
+            false => format!("(?{inline}){source}"),
+        };
+        let re = regex_lite::Regex::new(&pattern)
+            .map_err(|e| format!("Invalid regular expression /{source}/: {e}"))?;
+        Ok(Regex {
+            source: source.to_string(),
+            flags: flags.to_string(),
+            re,
+        })
+    }
+
+    fn is_global(&self) -> bool {
+        self.flags.contains('g')
+    }
+
+    /// The match and its groups like `exec`, or `null`.
+    fn exec(&self, s: &str) -> Result<Value, String> {
+        match self.re.captures(s) {
+            Some(caps) => Array::from_items(
+                caps.iter()
+                    .map(|it| it.map_or(Value::Undefined, |it| Value::Str(it.as_str().to_string())))
+                    .collect(),
+            ),
+            None => Ok(Value::Null),
+        }
+    }
+}
+
+pub struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+// ---------------------------------------------------------------------------
+// Lexer
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    Punct(&'static str),
+    /// Pattern and flags of a regular expression literal
+    Regex(String, String),
+    Eof,
+}
+
+const PUNCTS: [&str; 41] = [
+    "===", "!==", "==", "!=", "<=", ">=", "&&", "||", "++", "--", "+=", "-=", "*=", "/=", "{", "}",
+    "(", ")", "[", "]", ";", ",", ".", "?", ":", "=", "<", ">", "+", "-", "*", "/", "%", "!", "&",
+    "|", "^", "~", "<<", ">>", ">>>",
+];
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i + 1 < chars.len() && !(chars[i] == '*' && chars[i + 1] == '/') {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '/' && starts_regex(tokens.last()) {
+            let (token, end) = regex_literal(&chars, i)?;
+            tokens.push(token);
+            i = end;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            let start = i;
+            if c == '0' && matches!(chars.get(i + 1), Some('x' | 'X')) {
+                i += 2;
+                while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                    i += 1;
+                }
+                let text: String = chars[start + 2..i].iter().collect();
+                let value = i64::from_str_radix(&text, 16).map_err(|e| e.to_string())?;
+                tokens.push(Token::Num(value as f64));
+                continue;
+            }
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid number: {text}"))?;
+            tokens.push(Token::Num(value));
+        } else if c == '"' || c == '\'' {
+            let quote = c;
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err("Unterminated string literal".to_string()),
+                    Some(&ch) if ch == quote => break,
+                    Some('\\') => {
+                        i += 1;
+                        match chars.get(i) {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('r') => s.push('\r'),
+                            Some(&other) => s.push(other),
+                            None => return Err("Unterminated string literal".to_string()),
+                        }
+                    }
+                    Some(&ch) => s.push(ch),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push(Token::Str(s));
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 3)].iter().collect();
+            let punct = PUNCTS
+                .iter()
+                .filter(|p| rest.starts_with(*p))
+                .max_by_key(|p| p.len())
+                .ok_or_else(|| format!("Unexpected character: {c}"))?;
+            i += punct.chars().count();
+            tokens.push(Token::Punct(punct));
+        }
+    }
+
+    tokens.push(Token::Eof);
+    Ok(tokens)
+}
+
+/// Whether a `/` after `prev` starts a regular expression rather than a division.
+fn starts_regex(prev: Option<&Token>) -> bool {
+    match prev {
+        None => true,
+        Some(Token::Punct(p)) => !matches!(*p, ")" | "]"),
+        Some(Token::Ident(id)) => matches!(id.as_str(), "return" | "typeof" | "case" | "else"),
+        Some(_) => false,
+    }
+}
+
+/// The regular expression literal starting at `start`, and where it ends.
+fn regex_literal(chars: &[char], start: usize) -> Result<(Token, usize), String> {
+    let unterminated = || "Unterminated regular expression".to_string();
+    let mut pattern = String::new();
+    let mut in_class = false;
+    let mut i = start + 1;
+    loop {
+        match chars.get(i) {
+            None | Some('\n') => return Err(unterminated()),
+            Some('\\') => {
+                let escaped = *chars.get(i + 1).ok_or_else(unterminated)?;
+                // `\/` only keeps the delimiter from ending the literal
+                if escaped != '/' {
+                    pattern.push('\\');
+                }
+                pattern.push(escaped);
+                i += 1;
+            }
+            Some('/') if !in_class => break,
+            Some(&c) => {
+                match c {
+                    '[' => in_class = true,
+                    ']' => in_class = false,
+                    _ => {}
+                }
+                pattern.push(c);
+            }
+        }
+        i += 1;
+    }
+    let flags_start = i + 1;
+    let mut end = flags_start;
+    while end < chars.len() && chars[end].is_ascii_alphabetic() {
+        end += 1;
+    }
+    let flags = chars[flags_start..end].iter().collect();
+    Ok((Token::Regex(pattern, flags), end))
+}
+
+// ---------------------------------------------------------------------------
+// Parser
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Lit(Lit),
+    Ident(String),
+    Array(Vec<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Logical(&'static str, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    Assign(&'static str, Box<Expr>, Box<Expr>),
+    Update(&'static str, bool, Box<Expr>),
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum Lit {
+    Num(f64),
+    Str(String),
+    Regex(Rc<Regex>),
+    Bool(bool),
+    Null,
+    Undefined,
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Func(Rc<FunctionDecl>),
+    Var(Vec<(String, Option<Expr>)>),
+    Expr(Expr),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    /// The cases in order, `None` being `default`
+    Switch(Expr, Vec<(Option<Expr>, Vec<Stmt>)>),
+    While(Expr, Box<Stmt>),
+    For(Option<Box<Stmt>>, Option<Expr>, Option<Expr>, Box<Stmt>),
+    Block(Vec<Stmt>),
+    Return(Option<Expr>),
+    Break,
+    Continue,
+    Empty,
+}
+
+#[derive(Debug)]
+struct FunctionDecl {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+/// Deepest nesting of statements and expressions accepted, so a script full
+/// of brackets cannot overflow the stack while being parsed.
+const MAX_NESTING: usize = 128;
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    /// Run `parse` one nesting level deeper. Statements, assignments and unary
+    /// expressions go through here, as every recursion of the parser does.
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        if self.depth >= MAX_NESTING {
+            return Err("Script nested too deeply".to_string());
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Token::Punct(q) if *q == p)
+    }
+
+    fn is_keyword(&self, kw: &str) -> bool {
+        matches!(self.peek(), Token::Ident(id) if id == kw)
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<(), String> {
+        if self.eat_punct(p) {
+            Ok(())
+        } else {
+            Err(format!("Expected `{p}`, found {:?}", self.peek()))
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, String> {
+        match self.next() {
+            Token::Ident(id) => Ok(id),
+            other => Err(format!("Expected identifier, found {other:?}")),
+        }
+    }
+
+    fn end_statement(&mut self) {
+        self.eat_punct(";");
+    }
+
+    fn program(&mut self) -> Result<Vec<Stmt>, String> {
+        let mut stmts = Vec::new();
+        while *self.peek() != Token::Eof {
+            stmts.push(self.statement()?);
+        }
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, String> {
+        self.nested(Self::statement_inner)
+    }
+
+    fn statement_inner(&mut self) -> Result<Stmt, String> {
+        if self.eat_punct(";") {
+            return Ok(Stmt::Empty);
+        }
+        if self.is_punct("{") {
+            return Ok(Stmt::Block(self.block()?));
+        }
+        let keyword = match self.peek() {
+            Token::Ident(id) => id.clone(),
+            _ => String::new(),
+        };
+        match keyword.as_str() {
+            "function" => {
+                self.next();
+                let name = self.expect_ident()?;
+                let (params, body) = self.function_rest()?;
+                Ok(Stmt::Func(Rc::new(FunctionDecl { name, params, body })))
+            }
+            "var" | "let" | "const" => {
+                let stmt = self.var_decl()?;
+                self.end_statement();
+                Ok(stmt)
+            }
+            "if" => {
+                self.next();
+                self.expect_punct("(")?;
+                let cond = self.expression()?;
+                self.expect_punct(")")?;
+                let then = Box::new(self.statement()?);
+                let otherwise = if self.is_keyword("else") {
+                    self.next();
+                    Some(Box::new(self.statement()?))
+                } else {
+                    None
+                };
+                Ok(Stmt::If(cond, then, otherwise))
+            }
+            "switch" => {
+                self.next();
+                self.expect_punct("(")?;
+                let value = self.expression()?;
+                self.expect_punct(")")?;
+                self.expect_punct("{")?;
+                let mut cases = Vec::new();
+                while !self.eat_punct("}") {
+                    let test = match self.expect_ident()?.as_str() {
+                        "case" => Some(self.expression()?),
+                        "default" => None,
+                        other => return Err(format!("Expected `case`, found {other}")),
+                    };
+                    self.expect_punct(":")?;
+                    let mut body = Vec::new();
+                    while !(self.is_keyword("case")
+                        || self.is_keyword("default")
+                        || self.is_punct("}"))
+                    {
+                        if *self.peek() == Token::Eof {
+                            return Err("Unexpected end of script".to_string());
+                        }
+                        body.push(self.statement()?);
+                    }
+                    cases.push((test, body));
+                }
+                Ok(Stmt::Switch(value, cases))
+            }
+            "while" => {
+                self.next();
+                self.expect_punct("(")?;
+                let cond = self.expression()?;
+                self.expect_punct(")")?;
+                Ok(Stmt::While(cond, Box::new(self.statement()?)))
+            }
+            "for" => {
+                self.next();
+                self.expect_punct("(")?;
+                let init = if self.is_punct(";") {
+                    None
+                } else if self.is_keyword("var") || self.is_keyword("let") {
+                    Some(Box::new(self.var_decl()?))
+                } else {
+                    Some(Box::new(Stmt::Expr(self.expression()?)))
+                };
+                self.expect_punct(";")?;
+                let cond = match self.is_punct(";") {
+                    true => None,
+                    false => Some(self.expression()?),
+                };
+                self.expect_punct(";")?;
+                let update = match self.is_punct(")") {
+                    true => None,
+                    false => Some(self.expression()?),
+                };
+                self.expect_punct(")")?;
+                Ok(Stmt::For(init, cond, update, Box::new(self.statement()?)))
+            }
+            "return" => {
+                self.next();
+                let value = if self.is_punct(";") || self.is_punct("}") {
+                    None
+                } else {
+                    Some(self.expression()?)
+                };
+                self.end_statement();
+                Ok(Stmt::Return(value))
+            }
+            "break" => {
+                self.next();
+                self.end_statement();
+                Ok(Stmt::Break)
+            }
+            "continue" => {
+                self.next();
+                self.end_statement();
+                Ok(Stmt::Continue)
+            }
+            _ => {
+                let expr = self.expression()?;
+                self.end_statement();
+                Ok(Stmt::Expr(expr))
+            }
+        }
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, String> {
+        self.expect_punct("{")?;
+        let mut stmts = Vec::new();
+        while !self.is_punct("}") {
+            if *self.peek() == Token::Eof {
+                return Err("Unexpected end of script".to_string());
+            }
+            stmts.push(self.statement()?);
+        }
+        self.next();
+        Ok(stmts)
+    }
+
+    fn function_rest(&mut self) -> Result<(Vec<String>, Vec<Stmt>), String> {
+        self.expect_punct("(")?;
+        let mut params = Vec::new();
+        while !self.eat_punct(")") {
+            params.push(self.expect_ident()?);
+            if !self.is_punct(")") {
+                self.expect_punct(",")?;
+            }
+        }
+        Ok((params, self.block()?))
+    }
+
+    fn var_decl(&mut self) -> Result<Stmt, String> {
+        self.next();
+        let mut decls = Vec::new();
+        loop {
+            let name = self.expect_ident()?;
+            let init = match self.eat_punct("=") {
+                true => Some(self.assignment()?),
+                false => None,
+            };
+            decls.push((name, init));
+            if !self.eat_punct(",") {
+                break;
+            }
+        }
+        Ok(Stmt::Var(decls))
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        let mut expr = self.assignment()?;
+        while self.eat_punct(",") {
+            let rhs = self.assignment()?;
+            expr = Expr::Binary(",", Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, String> {
+        self.nested(Self::assignment_inner)
+    }
+
+    fn assignment_inner(&mut self) -> Result<Expr, String> {
+        let target = self.conditional()?;
+        for op in ["=", "+=", "-=", "*=", "/="] {
+            if self.eat_punct(op) {
+                if !matches!(target, Expr::Ident(_) | Expr::Member(..) | Expr::Index(..)) {
+                    return Err("Invalid assignment target".to_string());
+                }
+                let value = self.assignment()?;
+                let op = PUNCTS.iter().find(|p| **p == op).copied().unwrap_or("=");
+                return Ok(Expr::Assign(op, Box::new(target), Box::new(value)));
+            }
+        }
+        Ok(target)
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let cond = self.binary(0)?;
+        if self.eat_punct("?") {
+            let then = self.assignment()?;
+            self.expect_punct(":")?;
+            let otherwise = self.assignment()?;
+            return Ok(Expr::Cond(
+                Box::new(cond),
+                Box::new(then),
+                Box::new(otherwise),
+            ));
+        }
+        Ok(cond)
+    }
+
+    fn binary(&mut self, min_prec: u8) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some((op, prec)) = self.binary_operator(min_prec) {
+            self.next();
+            let rhs = self.binary(prec + 1)?;
+            lhs = match op {
+                "&&" | "||" => Expr::Logical(op, Box::new(lhs), Box::new(rhs)),
+                _ => Expr::Binary(op, Box::new(lhs), Box::new(rhs)),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn binary_operator(&self, min_prec: u8) -> Option<(&'static str, u8)> {
+        match self.peek() {
+            Token::Punct(op) => binary_precedence(op)
+                .filter(|prec| *prec >= min_prec)
+                .map(|prec| (*op, prec)),
+            _ => None,
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        self.nested(Self::unary_inner)
+    }
+
+    fn unary_inner(&mut self) -> Result<Expr, String> {
+        if self.is_keyword("typeof") {
+            self.next();
+            return Ok(Expr::Unary("typeof", Box::new(self.unary()?)));
+        }
+        for op in ["!", "-", "+", "~"] {
+            if self.eat_punct(op) {
+                let op = PUNCTS.iter().find(|p| **p == op).copied().unwrap_or("!");
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        for op in ["++", "--"] {
+            if self.eat_punct(op) {
+                let op = if op == "++" { "++" } else { "--" };
+                return Ok(Expr::Update(op, true, Box::new(self.unary()?)));
+            }
+        }
+        let expr = self.postfix()?;
+        for op in ["++", "--"] {
+            if self.eat_punct(op) {
+                let op = if op == "++" { "++" } else { "--" };
+                return Ok(Expr::Update(op, false, Box::new(expr)));
+            }
+        }
+        Ok(expr)
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat_punct(".") {
+                expr = Expr::Member(Box::new(expr), self.expect_ident()?);
+            } else if self.eat_punct("[") {
+                let index = self.expression()?;
+                self.expect_punct("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat_punct("(") {
+                let mut args = Vec::new();
+                while !self.eat_punct(")") {
+                    args.push(self.assignment()?);
+                    if !self.is_punct(")") {
+                        self.expect_punct(",")?;
+                    }
+                }
+                expr = Expr::Call(Box::new(expr), args);
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Token::Num(n) => Ok(Expr::Lit(Lit::Num(n))),
+            Token::Str(s) => Ok(Expr::Lit(Lit::Str(s))),
+            Token::Regex(pattern, flags) => Ok(Expr::Lit(Lit::Regex(Rc::new(Regex::new(
+                &pattern, &flags,
+            )?)))),
+            Token::Ident(id) => match id.as_str() {
+                "true" => Ok(Expr::Lit(Lit::Bool(true))),
+                "false" => Ok(Expr::Lit(Lit::Bool(false))),
+                "null" => Ok(Expr::Lit(Lit::Null)),
+                "undefined" => Ok(Expr::Lit(Lit::Undefined)),
+                _ => Ok(Expr::Ident(id)),
+            },
+            Token::Punct("(") => {
+                let expr = self.expression()?;
+                self.expect_punct(")")?;
+                Ok(expr)
+            }
+            Token::Punct("[") => {
+                let mut items = Vec::new();
+                while !self.eat_punct("]") {
+                    items.push(self.assignment()?);
+                    if !self.is_punct("]") {
+                        self.expect_punct(",")?;
+                    }
+                }
+                Ok(Expr::Array(items))
+            }
+            other => Err(format!("Unexpected token: {other:?}")),
+        }
+    }
+}
+
+fn binary_precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" | "===" | "!==" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" | ">>>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Interpreter
+// ---------------------------------------------------------------------------
+
+enum Flow {
+    Normal,
+    Return(Value),
+    Break,
+    Continue,
+}
+
+/// Upper bound on evaluated expressions, calls and loop iterations, and on
+/// call depth, so a hostile or broken script cannot hang shell startup.
+const MAX_STEPS: usize = 1_000_000;
+const MAX_DEPTH: usize = 256;
+/// Longest string and array a script may build.
+const MAX_STRING_LEN: usize = 1 << 20;
+const MAX_ARRAY_LEN: usize = 100_000;
+
+#[derive(Default)]
+pub struct Context {
+    globals: HashMap<String, Value>,
+    locals: Vec<HashMap<String, Value>>,
+    steps: usize,
+    /// Wall-clock limit of each `eval` and `call`, next to `MAX_STEPS`
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl Context {
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Context {
+            timeout: Some(timeout),
+            ..Default::default()
+        }
+    }
+
+    pub fn define_native(&mut self, name: &str, func: impl Fn(&[Value]) -> Value + 'static) {
+        self.globals
+            .insert(name.to_string(), Value::Native(Rc::new(func)));
+    }
+
+    /// Parse and run a script, defining its top-level functions and variables.
+    pub fn eval(&mut self, src: &str) -> Result<(), String> {
+        let stmts = Parser::new(tokenize(src)?).program()?;
+        self.start();
+        self.hoist(&stmts);
+        self.exec_block(&stmts)?;
+        Ok(())
+    }
+
+    /// Call a global function by name.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, String> {
+        self.start();
+        let func = self
+            .globals
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("`{name}` is not defined"))?;
+        self.call_value(&func, args.to_vec())
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.globals.contains_key(name)
+    }
+
+    fn hoist(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            if let Stmt::Func(decl) = stmt {
+                let func = Value::Func(Rc::new(Function {
+                    name: decl.name.clone(),
+                    params: decl.params.clone(),
+                    body: decl.body.clone(),
+                }));
+                self.declare(&decl.name, func);
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &str, value: Value) {
+        match self.locals.last_mut() {
+            Some(scope) => scope.insert(name.to_string(), value),
+            None => self.globals.insert(name.to_string(), value),
+        };
+    }
+
+    fn lookup(&self, name: &str) -> Result<Value, String> {
+        if let Some(value) = self.locals.last().and_then(|scope| scope.get(name)) {
+            return Ok(value.clone());
+        }
+        self.globals
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("`{name}` is not defined"))
+    }
+
+    fn set(&mut self, name: &str, value: Value) {
+        if let Some(scope) = self.locals.last_mut() {
+            if let Some(slot) = scope.get_mut(name) {
+                *slot = value;
+                return;
+            }
+        }
+        self.globals.insert(name.to_string(), value);
+    }
+
+    fn start(&mut self) {
+        self.steps = 0;
+        self.deadline = self.timeout.map(|it| Instant::now() + it);
+    }
+
+    fn tick(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err("Script exceeded the execution limit".to_string());
+        }
+        let expired = || self.deadline.is_some_and(|it| Instant::now() > it);
+        if self.steps.is_multiple_of(1024) && expired() {
+            return Err("Script exceeded the time limit".to_string());
+        }
+        Ok(())
+    }
+
+    fn exec_block(&mut self, stmts: &[Stmt]) -> Result<Flow, String> {
+        for stmt in stmts {
+            match self.exec(stmt)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&mut self, stmt: &Stmt) -> Result<Flow, String> {
+        match stmt {
+            Stmt::Func(_) | Stmt::Empty => {}
+            Stmt::Var(decls) => {
+                for (name, init) in decls {
+                    let value = match init {
+                        Some(expr) => self.eval_expr(expr)?,
+                        None => Value::Undefined,
+                    };
+                    self.declare(name, value);
+                }
+            }
+            Stmt::Expr(expr) => {
+                self.eval_expr(expr)?;
+            }
+            Stmt::If(cond, then, otherwise) => {
+                if self.eval_expr(cond)?.truthy() {
+                    return self.exec(then);
+                } else if let Some(otherwise) = otherwise {
+                    return self.exec(otherwise);
+                }
+            }
+            Stmt::Switch(value, cases) => {
+                let value = self.eval_expr(value)?;
+                let mut start = None;
+                for (i, (test, _)) in cases.iter().enumerate() {
+                    if let Some(test) = test {
+                        if self.eval_expr(test)?.strict_eq(&value) {
+                            start = Some(i);
+                            break;
+                        }
+                    }
+                }
+                let start = start.or_else(|| cases.iter().position(|(test, _)| test.is_none()));
+                // Without a `break`, each case falls through to the next one
+                for (_, body) in cases.iter().skip(start.unwrap_or(cases.len())) {
+                    match self.exec_block(body)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Stmt::While(cond, body) => {
+                while self.eval_expr(cond)?.truthy() {
+                    self.tick()?;
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+            }
+            Stmt::For(init, cond, update, body) => {
+                if let Some(init) = init {
+                    self.exec(init)?;
+                }
+                loop {
+                    if let Some(cond) = cond {
+                        if !self.eval_expr(cond)?.truthy() {
+                            break;
+                        }
+                    }
+                    self.tick()?;
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                    if let Some(update) = update {
+                        self.eval_expr(update)?;
+                    }
+                }
+            }
+            Stmt::Block(stmts) => return self.exec_block(stmts),
+            Stmt::Return(value) => {
+                let value = match value {
+                    Some(expr) => self.eval_expr(expr)?,
+                    None => Value::Undefined,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Stmt::Break => return Ok(Flow::Break),
+            Stmt::Continue => return Ok(Flow::Continue),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn call_value(&mut self, func: &Value, args: Vec<Value>) -> Result<Value, String> {
+        self.tick()?;
+        match func {
+            Value::Native(native) => Ok(native(&args)),
+            Value::Func(func) => {
+                if self.locals.len() >= MAX_DEPTH {
+                    return Err("Maximum call depth exceeded".to_string());
+                }
+                let mut scope = HashMap::new();
+                let mut args = args.into_iter();
+                for param in &func.params {
+                    scope.insert(param.clone(), args.next().unwrap_or(Value::Undefined));
+                }
+                self.locals.push(scope);
+                self.hoist(&func.body);
+                let result = self.exec_block(&func.body);
+                self.locals.pop();
+                match result? {
+                    Flow::Return(value) => Ok(value),
+                    _ => Ok(Value::Undefined),
+                }
+            }
+            other => Err(format!("{other:?} is not a function")),
+        }
+    }
+
+    fn eval_expr(&mut self, expr: &Expr) -> Result<Value, String> {
+        self.tick()?;
+        Ok(match expr {
+            Expr::Lit(lit) => match lit {
+                Lit::Num(n) => Value::Num(*n),
+                Lit::Str(s) => Value::Str(s.clone()),
+                Lit::Regex(re) => Value::Regex(re.clone()),
+                Lit::Bool(b) => Value::Bool(*b),
+                Lit::Null => Value::Null,
+                Lit::Undefined => Value::Undefined,
+            },
+            Expr::Ident(name) => self.lookup(name)?,
+            Expr::Array(items) => Array::from_items(
+                items
+                    .iter()
+                    .map(|it| self.eval_expr(it))
+                    .collect::<Result<_, _>>()?,
+            )?,
+            Expr::Unary(op, operand) => {
+                if *op == "typeof" {
+                    if let Expr::Ident(name) = operand.as_ref() {
+                        let value = self.lookup(name).unwrap_or(Value::Undefined);
+                        return Ok(Value::Str(value.type_of().to_string()));
+                    }
+                }
+                let value = self.eval_expr(operand)?;
+                match *op {
+                    "!" => Value::Bool(!value.truthy()),
+                    "-" => Value::Num(-value.to_num()),
+                    "+" => Value::Num(value.to_num()),
+                    "~" => Value::Num(!(value.to_num() as i32) as f64),
+                    _ => Value::Str(value.type_of().to_string()),
+                }
+            }
+            Expr::Logical(op, lhs, rhs) => {
+                let lhs = self.eval_expr(lhs)?;
+                match (*op, lhs.truthy()) {
+                    ("&&", false) | ("||", true) => lhs,
+                    _ => self.eval_expr(rhs)?,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval_expr(lhs)?;
+                let rhs = self.eval_expr(rhs)?;
+                binary_op(op, lhs, rhs)?
+            }
+            Expr::Cond(cond, then, otherwise) => match self.eval_expr(cond)?.truthy() {
+                true => self.eval_expr(then)?,
+                false => self.eval_expr(otherwise)?,
+            },
+            Expr::Assign(op, target, value) => {
+                let mut value = self.eval_expr(value)?;
+                if *op != "=" {
+                    let current = self.eval_expr(target)?;
+                    value = binary_op(&op[..1], current, value)?;
+                }
+                self.assign(target, value.clone())?;
+                value
+            }
+            Expr::Update(op, prefix, target) => {
+                let old = self.eval_expr(target)?.to_num();
+                let new = if *op == "++" { old + 1.0 } else { old - 1.0 };
+                self.assign(target, Value::Num(new))?;
+                Value::Num(if *prefix { new } else { old })
+            }
+            Expr::Member(object, name) => {
+                let object = self.eval_expr(object)?;
+                get_property(&object, name)
+            }
+            Expr::Index(object, index) => {
+                let object = self.eval_expr(object)?;
+                let index = self.eval_expr(index)?;
+                match (&object, &index) {
+                    (Value::Array(array), Value::Num(n)) => {
+                        let items = array.items.borrow();
+                        to_index(*n, items.len()).map_or(Value::Undefined, |i| items[i].clone())
+                    }
+                    (Value::Str(s), Value::Num(n)) => to_index(*n, usize::MAX)
+                        .and_then(|i| s.chars().nth(i))
+                        .map_or(Value::Undefined, |c| Value::Str(c.to_string())),
+                    _ => get_property(&object, &index.to_str()),
+                }
+            }
+            Expr::Call(callee, args) => {
+                let args = args
+                    .iter()
+                    .map(|it| self.eval_expr(it))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Expr::Member(object, name) = callee.as_ref() {
+                    let object = self.eval_expr(object)?;
+                    return call_method(&object, name, &args);
+                }
+                let func = self.eval_expr(callee)?;
+                self.call_value(&func, args)?
+            }
+        })
+    }
+
+    fn assign(&mut self, target: &Expr, value: Value) -> Result<(), String> {
+        match target {
+            Expr::Ident(name) => {
+                self.set(name, value);
+                Ok(())
+            }
+            Expr::Index(object, index) => {
+                let object = self.eval_expr(object)?;
+                let index = self.eval_expr(index)?.to_num();
+                let Value::Array(array) = object else {
+                    return Err("Unsupported index assignment".to_string());
+                };
+                let index = to_index(index, MAX_ARRAY_LEN)
+                    .ok_or_else(|| format!("Array index {index} out of range"))?;
+                array.check_item(&value)?;
+                let mut items = array.items.borrow_mut();
+                if index >= items.len() {
+                    items.resize(index + 1, Value::Undefined);
+                }
+                items[index] = value;
+                Ok(())
+            }
+            _ => Err("Unsupported assignment target".to_string()),
+        }
+    }
+}
+
+/// An index below `len`, which JavaScript only takes from non-negative integers.
+fn to_index(n: f64, len: usize) -> Option<usize> {
+    (n >= 0.0 && n.fract() == 0.0 && n < len as f64).then_some(n as usize)
+}
+
+/// `s` as a value, unless it is longer than `MAX_STRING_LEN`.
+fn string(s: String) -> Result<Value, String> {
+    match s.len() > MAX_STRING_LEN {
+        true => Err("String too long".to_string()),
+        false => Ok(Value::Str(s)),
+    }
+}
+
+fn binary_op(op: &str, lhs: Value, rhs: Value) -> Result<Value, String> {
+    Ok(match op {
+        "+" => match (&lhs, &rhs) {
+            (Value::Str(_), _)
+            | (_, Value::Str(_))
+            | (Value::Array(_), _)
+            | (_, Value::Array(_)) => string(lhs.to_str() + &rhs.to_str())?,
+            _ => Value::Num(lhs.to_num() + rhs.to_num()),
+        },
+        "-" => Value::Num(lhs.to_num() - rhs.to_num()),
+        "*" => Value::Num(lhs.to_num() * rhs.to_num()),
+        "/" => Value::Num(lhs.to_num() / rhs.to_num()),
+        "%" => Value::Num(lhs.to_num() % rhs.to_num()),
+        "==" => Value::Bool(lhs.loose_eq(&rhs)),
+        "!=" => Value::Bool(!lhs.loose_eq(&rhs)),
+        "===" => Value::Bool(lhs.strict_eq(&rhs)),
+        "!==" => Value::Bool(!lhs.strict_eq(&rhs)),
+        "<" | ">" | "<=" | ">=" => {
+            let ordering = match (&lhs, &rhs) {
+                (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+                _ => lhs.to_num().partial_cmp(&rhs.to_num()),
+            };
+            Value::Bool(match ordering {
+                None => false,
+                Some(ordering) => match op {
+                    "<" => ordering.is_lt(),
+                    ">" => ordering.is_gt(),
+                    "<=" => ordering.is_le(),
+                    _ => ordering.is_ge(),
+                },
+            })
+        }
+        "&" => Value::Num((to_u32(&lhs) & to_u32(&rhs)) as i32 as f64),
+        "|" => Value::Num((to_u32(&lhs) | to_u32(&rhs)) as i32 as f64),
+        "^" => Value::Num((to_u32(&lhs) ^ to_u32(&rhs)) as i32 as f64),
+        "<<" => Value::Num(((to_u32(&lhs) as i32) << (to_u32(&rhs) & 31)) as f64),
+        ">>" => Value::Num(((to_u32(&lhs) as i32) >> (to_u32(&rhs) & 31)) as f64),
+        ">>>" => Value::Num((to_u32(&lhs) >> (to_u32(&rhs) & 31)) as f64),
+        _ => rhs,
+    })
+}
+
+fn to_u32(value: &Value) -> u32 {
+    let n = value.to_num();
+    if n.is_finite() {
+        n as i64 as u32
+    } else {
+        0
+    }
+}
+
+fn get_property(object: &Value, name: &str) -> Value {
+    match (object, name) {
+        (Value::Str(s), "length") => Value::Num(s.chars().count() as f64),
+        (Value::Array(array), "length") => Value::Num(array.items.borrow().len() as f64),
+        _ => Value::Undefined,
+    }
+}
+
+fn arg(args: &[Value], idx: usize) -> Value {
+    args.get(idx).cloned().unwrap_or(Value::Undefined)
+}
+
+/// Clamp a JavaScript index argument into `0..=len`.
+fn clamp_index(value: &Value, len: usize, default: usize) -> usize {
+    match value {
+        Value::Undefined => default,
+        _ => {
+            let n = value.to_num();
+            if n.is_nan() || n < 0.0 {
+                0
+            } else {
+                (n as usize).min(len)
+            }
+        }
+    }
+}
+
+/// The regular expression a `match` or `search` argument stands for.
+fn to_regex(value: &Value) -> Result<Rc<Regex>, String> {
+    match value {
+        Value::Regex(re) => Ok(re.clone()),
+        other => Ok(Rc::new(Regex::new(&other.to_str(), "")?)),
+    }
+}
+
+/// A `replace` replacement with JavaScript's `$&` and `$1` in the `${0}` and
+/// `${1}` syntax of `regex_lite`.
+fn replacement(to: &str) -> String {
+    let mut res = String::with_capacity(to.len());
+    let mut chars = to.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            res.push(c);
+            continue;
+        }
+        match chars.peek().copied() {
+            Some('&') => res.push_str("${0}"),
+            Some(digit) if digit.is_ascii_digit() => res.push_str(&format!("${{{digit}}}")),
+            Some('$') => res.push_str("$$"),
+            _ => {
+                res.push_str("$$");
+                continue;
+            }
+        }
+        chars.next();
+    }
+    res
+}
+
+/// Call a built-in method. Arrays are shared, so `push` updates them in place.
+fn call_method(object: &Value, name: &str, args: &[Value]) -> Result<Value, String> {
+    let result = match object {
+        Value::Str(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            let len = chars.len();
+            match name {
+                "toLowerCase" => Value::Str(s.to_lowercase()),
+                "toUpperCase" => Value::Str(s.to_uppercase()),
+                "trim" => Value::Str(s.trim().to_string()),
+                "toString" => Value::Str(s.clone()),
+                "charAt" => {
+                    let idx = clamp_index(&arg(args, 0), len, 0);
+                    Value::Str(chars.get(idx).map(|c| c.to_string()).unwrap_or_default())
+                }
+                "indexOf" | "lastIndexOf" | "includes" | "startsWith" | "endsWith" => {
+                    let needle = arg(args, 0).to_str();
+                    match name {
+                        "includes" => Value::Bool(s.contains(&needle)),
+                        "startsWith" => Value::Bool(s.starts_with(&needle)),
+                        "endsWith" => Value::Bool(s.ends_with(&needle)),
+                        _ => {
+                            let found = match name {
+                                "indexOf" => s.find(&needle),
+                                _ => s.rfind(&needle),
+                            };
+                            Value::Num(match found {
+                                Some(byte) => s[..byte].chars().count() as f64,
+                                None => -1.0,
+                            })
+                        }
+                    }
+                }
+                "substring" => {
+                    let a = clamp_index(&arg(args, 0), len, 0);
+                    let b = clamp_index(&arg(args, 1), len, len);
+                    let (a, b) = (a.min(b), a.max(b));
+                    Value::Str(chars[a..b].iter().collect())
+                }
+                "substr" | "slice" => {
+                    let start = arg(args, 0).to_num();
+                    let start = if start < 0.0 {
+                        len.saturating_sub((-start) as usize)
+                    } else {
+                        clamp_index(&arg(args, 0), len, 0)
+                    };
+                    let end = match (name, arg(args, 1)) {
+                        (_, Value::Undefined) => len,
+                        ("substr", count) => start
+                            .saturating_add(count.to_num().max(0.0) as usize)
+                            .min(len),
+                        (_, end) if end.to_num() < 0.0 => {
+                            len.saturating_sub((-end.to_num()) as usize)
+                        }
+                        (_, end) => clamp_index(&end, len, len),
+                    };
+                    Value::Str(chars[start..end.max(start)].iter().collect())
+                }
+                "match" if to_regex(&arg(args, 0))?.is_global() => {
+                    let re = to_regex(&arg(args, 0))?;
+                    let found: Vec<_> = re
+                        .re
+                        .find_iter(s)
+                        .map(|it| Value::Str(it.as_str().to_string()))
+                        .collect();
+                    match found.is_empty() {
+                        true => Value::Null,
+                        false => Array::from_items(found)?,
+                    }
+                }
+                "match" => to_regex(&arg(args, 0))?.exec(s)?,
+                "search" => {
+                    let re = to_regex(&arg(args, 0))?;
+                    Value::Num(
+                        re.re
+                            .find(s)
+                            .map_or(-1.0, |it| s[..it.start()].chars().count() as f64),
+                    )
+                }
+                "split" => match arg(args, 0) {
+                    Value::Undefined => Array::from_items(vec![Value::Str(s.clone())])?,
+                    Value::Regex(re) => Array::from_items(
+                        re.re
+                            .split(s)
+                            .map(|it| Value::Str(it.to_string()))
+                            .collect(),
+                    )?,
+                    sep => {
+                        let sep = sep.to_str();
+                        let parts: Vec<Value> = if sep.is_empty() {
+                            chars.iter().map(|c| Value::Str(c.to_string())).collect()
+                        } else {
+                            s.split(sep.as_str())
+                                .map(|p| Value::Str(p.to_string()))
+                                .collect()
+                        };
+                        Array::from_items(parts)?
+                    }
+                },
+                "replace" if matches!(arg(args, 0), Value::Regex(_)) => {
+                    let re = to_regex(&arg(args, 0))?;
+                    let to = replacement(&arg(args, 1).to_str());
+                    match re.is_global() {
+                        true => Value::Str(re.re.replace_all(s, to.as_str()).into_owned()),
+                        false => Value::Str(re.re.replace(s, to.as_str()).into_owned()),
+                    }
+                }
+                "replace" => {
+                    let from = arg(args, 0).to_str();
+                    let to = arg(args, 1).to_str();
+                    Value::Str(s.replacen(&from, &to, 1))
+                }
+                _ => return Err(format!("Unsupported string method: {name}")),
+            }
+        }
+        Value::Array(array) => match name {
+            "join" => {
+                let sep = match arg(args, 0) {
+                    Value::Undefined => ",".to_string(),
+                    sep => sep.to_str(),
+                };
+                Value::Str(array.join(&sep))
+            }
+            "indexOf" => {
+                let needle = arg(args, 0);
+                Value::Num(
+                    array
+                        .items
+                        .borrow()
+                        .iter()
+                        .position(|it| it.strict_eq(&needle))
+                        .map(|idx| idx as f64)
+                        .unwrap_or(-1.0),
+                )
+            }
+            "push" => {
+                for value in args {
+                    array.check_item(value)?;
+                }
+                let mut items = array.items.borrow_mut();
+                if items.len() + args.len() > MAX_ARRAY_LEN {
+                    return Err("Array too large".to_string());
+                }
+                items.extend(args.iter().cloned());
+                Value::Num(items.len() as f64)
+            }
+            _ => return Err(format!("Unsupported array method: {name}")),
+        },
+        Value::Regex(re) => match name {
+            "test" => Value::Bool(re.re.is_match(&arg(args, 0).to_str())),
+            "exec" => re.exec(&arg(args, 0).to_str())?,
+            "toString" => Value::Str(object.to_str()),
+            _ => return Err(format!("Unsupported regular expression method: {name}")),
+        },
+        Value::Num(_) | Value::Bool(_) if name == "toString" => Value::Str(object.to_str()),
+        _ => return Err(format!("Cannot call method `{name}` on {object:?}")),
+    };
+    match result {
+        Value::Str(s) => string(s),
+        result => Ok(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(src: &str, func: &str, args: &[Value]) -> Value {
+        let mut ctx = Context::default();
+        ctx.eval(src).unwrap();
+        ctx.call(func, args).unwrap()
+    }
+
+    #[test]
+    fn test_arithmetic_and_strings() {
+        let src = "function f(a, b) { return a + b * 2; }";
+        assert_eq!(
+            run(src, "f", &[Value::Num(1.0), Value::Num(3.0)]).to_str(),
+            "7"
+        );
+        assert_eq!(
+            run(src, "f", &[Value::Str("x".into()), Value::Num(3.0)]).to_str(),
+            "x6"
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let src = r#"
+            var suffix = ".corp";
+            function f(host) {
+                var n = 0;
+                for (var i = 0; i < host.length; i++) {
+                    if (host.charAt(i) == ".") n++;
+                }
+                if (host.endsWith(suffix) && n > 1) {
+                    return "PROXY p:" + n;
+                } else if (!host) {
+                    return "none";
+                }
+                return "DIRECT";
+            }
+        "#;
+        assert_eq!(
+            run(src, "f", &[Value::Str("a.b.corp".into())]).to_str(),
+            "PROXY p:2"
+        );
+        assert_eq!(
+            run(src, "f", &[Value::Str("a.corp".into())]).to_str(),
+            "DIRECT"
+        );
+        assert_eq!(run(src, "f", &[Value::Str("".into())]).to_str(), "none");
+    }
+
+    #[test]
+    fn test_arrays_and_natives() {
+        let src = r#"
+            function f(host) {
+                var parts = host.split(".");
+                var out = [];
+                out.push(parts[0].toUpperCase());
+                out.push(double(parts.length));
+                return out.join("-");
+            }
+        "#;
+        let mut ctx = Context::default();
+        ctx.define_native("double", |args| Value::Num(args[0].to_num() * 2.0));
+        ctx.eval(src).unwrap();
+        let result = ctx
+            .call("f", &[Value::Str("www.example.com".into())])
+            .unwrap();
+        assert_eq!(result.to_str(), "WWW-6");
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut ctx = Context::default();
+        assert!(ctx.eval("function f( { }").is_err());
+        ctx.eval("function f() { while (true) {} }").unwrap();
+        assert!(ctx.call("f", &[]).is_err());
+        assert!(ctx.call("missing", &[]).is_err());
+
+        ctx.eval("function g() { var a = []; a[1/0] = 1; return a; }")
+            .unwrap();
+        assert!(ctx.call("g", &[]).is_err());
+        ctx.eval("function h() { var a = []; a[1000000000000] = 1; return a; }")
+            .unwrap();
+        assert!(ctx.call("h", &[]).is_err());
+        ctx.eval("function s() { return 'xyz'.substr(1, 1/0); }")
+            .unwrap();
+        assert_eq!(ctx.call("s", &[]).unwrap().to_str(), "yz");
+    }
+
+    #[test]
+    fn test_regex() {
+        let src = r#"
+            function f(host) {
+                if (/^10\./.test(host)) return "private";
+                if (/\.CORP\.example$/i.test(host)) return "corp";
+                var parts = host.match(/^(\w+)\.(\w+)/);
+                var path = "a/b/c".replace(/\//g, "-");
+                return [parts[2], host.search(/[0-9]/), host.replace(/(\w+)\./, "$1-$&"), path,
+                    host.split(/\./).length, "a1b22".match(/[0-9]+/g).join("+"), 4 / 2 / 1].join(" ");
+            }
+        "#;
+        let call = |host: &str| run(src, "f", &[Value::Str(host.into())]).to_str();
+        assert_eq!(call("10.1.2.3"), "private");
+        assert_eq!(call("www.corp.Example"), "corp");
+        assert_eq!(
+            call("www.example9.com"),
+            "example9 11 www-www.example9.com a-b-c 3 1+22 2"
+        );
+
+        let mut ctx = Context::default();
+        assert!(ctx.eval("var r = /(?<=a)b/;").is_err());
+        assert!(ctx.eval("var r = /abc;").is_err());
+    }
+
+    #[test]
+    fn test_switch() {
+        let src = r#"
+            function f(host) {
+                var res = "";
+                switch (host.split(".")[0]) {
+                    case "www":
+                        res += "www ";
+                    case "api":
+                        res += "api";
+                        break;
+                    default:
+                        res = "other";
+                    case "ftp":
+                        return "ftp " + res;
+                }
+                return res;
+            }
+        "#;
+        let call = |host: &str| run(src, "f", &[Value::Str(host.into())]).to_str();
+        assert_eq!(call("www.example"), "www api");
+        assert_eq!(call("api.example"), "api");
+        assert_eq!(call("ftp.example"), "ftp ");
+        assert_eq!(call("mail.example"), "ftp other");
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut ctx = Context::default();
+        ctx.eval("function f(n) { return n && f(n - 1) + f(n - 1); }")
+            .unwrap();
+        assert!(ctx.call("f", &[Value::Num(40.0)]).is_err());
+        ctx.eval("function s() { var s = 'ab'; while (true) s += s; }")
+            .unwrap();
+        assert_eq!(ctx.call("s", &[]).unwrap_err(), "String too long");
+        ctx.eval("function a() { var a = []; while (true) a.push(1); }")
+            .unwrap();
+        assert_eq!(ctx.call("a", &[]).unwrap_err(), "Array too large");
+
+        let mut ctx = Context::with_timeout(Duration::ZERO);
+        ctx.eval("function f() { for (var i = 0; i < 10000; i++) {} }")
+            .unwrap();
+        assert_eq!(
+            ctx.call("f", &[]).unwrap_err(),
+            "Script exceeded the time limit"
+        );
+
+        let nested = |open: &str, close: &str| {
+            format!(
+                "var x = {}1{};",
+                open.repeat(100_000),
+                close.repeat(100_000)
+            )
+        };
+        let mut ctx = Context::default();
+        assert_eq!(
+            ctx.eval(&nested("(", ")")).unwrap_err(),
+            "Script nested too deeply"
+        );
+        assert!(ctx.eval(&nested("[", "]")).is_err());
+        assert!(ctx.eval(&nested("!", "")).is_err());
+        assert!(ctx.eval(&nested("{", "}").replace("var x = ", "")).is_err());
+        let x = format!("var x = {}1{};", "(".repeat(60), ")".repeat(60));
+        assert!(ctx.eval(&x).is_ok());
+    }
+
+    #[test]
+    fn test_shared_arrays() {
+        let src = r#"
+            function f() {
+                var a = [];
+                var b = a;
+                for (var i = 0; i < 50000; i++) b.push(i);
+                a[a.length] = "last";
+                var nets = [["10.0.0.0", "255.0.0.0"], ["192.168.0.0", "255.255.0.0"]];
+                return [a.length, a[-1], a[0.5], a[50000], nets[1][0], a === b].join(" ");
+            }
+            function cycle() { var a = [1]; a.push(a); }
+            function deep() { var a = [[1]]; var b = []; b.push(a); }
+        "#;
+        let mut ctx = Context::default();
+        ctx.eval(src).unwrap();
+        assert_eq!(
+            ctx.call("f", &[]).unwrap().to_str(),
+            "50001 undefined undefined last 192.168.0.0 true"
+        );
+        assert!(ctx.call("cycle", &[]).is_err());
+        assert!(ctx.call("deep", &[]).is_err());
+    }
+}
@@ -0,0 +1,654 @@
+//! Proxy auto-config (PAC) support: loading a script and evaluating
+//! `FindProxyForURL` with the standard helper functions, or `FindProxyForURLEx`
+//! with Microsoft's IPv6-aware `*Ex` ones.
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod js;
+pub mod wpad;
+
+use js::{Context, Value};
+
+use crate::no_proxy;
+
+/// Resolves a host name to its addresses, IPv4 first; used by `dnsResolve`,
+/// `isInNet`, etc.
+pub type Resolver = fn(&str) -> Vec<IpAddr>;
+
+/// Largest PAC response accepted, headers included.
+const MAX_PAC_SIZE: u64 = 4 << 20;
+
+pub struct Pac {
+    ctx: Context,
+}
+
+impl Pac {
+    /// Download (or read) the script at `url` and prepare it for evaluation.
+    /// Running the script and each `FindProxyForURL` call get `timeout` too.
+    pub fn load(url: &str, timeout: Duration) -> Result<Self, String> {
+        let script = fetch(url, timeout)?;
+        Self::new(&script, timeout)
+    }
+
+    pub fn new(script: &str, timeout: Duration) -> Result<Self, String> {
+        Self::with_resolver(script, system_resolve, timeout)
+    }
+
+    pub fn with_resolver(
+        script: &str,
+        resolver: Resolver,
+        timeout: Duration,
+    ) -> Result<Self, String> {
+        let mut ctx = Context::with_timeout(timeout);
+        define_helpers(&mut ctx, resolver);
+        ctx.eval(script)?;
+        Ok(Self { ctx })
+    }
+
+    /// Evaluate `FindProxyForURL` for `url` and return the first proxy as a
+    /// URL, or an empty string for `DIRECT`.
+    #[cfg(test)]
+    pub fn find_proxy(&mut self, url: &str) -> Result<String, String> {
+        parse_result(&self.evaluate(url)?)
+    }
+
+    /// The raw result of `FindProxyForURL` for `url`, e.g. `PROXY a:8080; DIRECT`.
+    /// Scripts that define `FindProxyForURLEx` get that called instead.
+    pub fn evaluate(&mut self, url: &str) -> Result<String, String> {
+        let host = host_of(url);
+        let name = match self.ctx.is_defined("FindProxyForURLEx") {
+            true => "FindProxyForURLEx",
+            false => "FindProxyForURL",
+        };
+        let result = self.ctx.call(
+            name,
+            &[Value::Str(url.to_string()), Value::Str(host.to_string())],
+        )?;
+        Ok(result.to_str())
+    }
+}
+
+/// Convert a PAC result such as `PROXY a:8080; DIRECT` into a proxy URL,
+/// taking the first entry.
+pub fn parse_result(result: &str) -> Result<String, String> {
+    let first = result.split(';').next().unwrap_or_default().trim();
+    let mut parts = first.split_whitespace();
+    let kind = parts.next().unwrap_or("DIRECT").to_ascii_uppercase();
+    let addr = parts.next();
+    let scheme = match kind.as_str() {
+        "DIRECT" => return Ok(String::new()),
+        "PROXY" | "HTTP" => "http",
+        "HTTPS" => "https",
+        "SOCKS" => "socks",
+        "SOCKS4" => "socks4",
+        "SOCKS5" => "socks5",
+        _ => return Err(format!("Invalid PAC result: {first}")),
+    };
+    match addr {
+        Some(addr) => Ok(format!("{scheme}://{addr}")),
+        None => Err(format!("Missing address in PAC result: {first}")),
+    }
+}
+
+/// Extract the host part of a URL (or return the input if it is a bare host).
+pub fn host_of(url: &str) -> &str {
+    let rest = match url.find("://") {
+        Some(idx) => &url[idx + 3..],
+        None => url,
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let authority = match authority.rfind('@') {
+        Some(idx) => &authority[idx + 1..],
+        None => authority,
+    };
+    if let Some(stripped) = authority.strip_prefix('[') {
+        return stripped.split(']').next().unwrap_or_default();
+    }
+    authority.split(':').next().unwrap_or_default()
+}
+
+/// Read a PAC script from a `file://` path or a plain `http://` URL.
+pub fn fetch(url: &str, timeout: Duration) -> Result<String, String> {
+    if let Some(path) = url.strip_prefix("file://") {
+        // `file:///C:/proxy.pac` on Windows
+        let path = match path.as_bytes() {
+            [b'/', _, b':', ..] => &path[1..],
+            _ => path,
+        };
+        return std::fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"));
+    }
+    if let Some(rest) = url.strip_prefix("http://") {
+        return http_get(rest, timeout);
+    }
+    if url.starts_with("https://") {
+        return Err(format!(
+            "HTTPS PAC URLs are not supported, serve the script over http:// or file://: {url}"
+        ));
+    }
+    Err(format!("Unsupported PAC URL: {url}"))
+}
+
+fn http_get(rest: &str, timeout: Duration) -> Result<String, String> {
+    let deadline = Instant::now() + timeout;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+    let addr = match authority.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() && !authority.ends_with(']') => {
+            authority.to_string()
+        }
+        _ => format!("{authority}:80"),
+    };
+    let lookup = move || addr.to_socket_addrs().map(|mut it| it.next());
+    let addr = match before(deadline, lookup) {
+        Some(Ok(Some(addr))) => addr,
+        Some(Ok(None)) => return Err(format!("Failed to resolve {authority}")),
+        Some(Err(e)) => return Err(format!("Failed to resolve {authority}: {e}")),
+        None => return Err(format!("Timed out resolving {authority}")),
+    };
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    http_get_from(addr, authority, path, remaining)
+}
+
+/// Issue a `GET` for `path` against an already resolved address, giving up
+/// once `timeout` has elapsed or the response exceeds `MAX_PAC_SIZE`.
+fn http_get_from(
+    addr: SocketAddr,
+    authority: &str,
+    path: &str,
+    timeout: Duration,
+) -> Result<String, String> {
+    let deadline = Instant::now() + timeout;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)
+        .map_err(|e| format!("Failed to connect to {authority}: {e}"))?;
+    stream.set_write_timeout(Some(timeout)).ok();
+    let request = format!("GET {path} HTTP/1.0\r\nHost: {authority}\r\nConnection: close\r\n\r\n");
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| format!("Failed to send request to {authority}: {e}"))?;
+
+    // The read timeout only bounds each `read`, so a server trickling bytes
+    // is cut off by the overall deadline instead.
+    let mut response = Vec::new();
+    let mut reader = (&stream).take(MAX_PAC_SIZE + 1);
+    let mut buf = [0; 8192];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(format!("Timed out reading the response from {authority}"));
+        }
+        stream.set_read_timeout(Some(remaining)).ok();
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(format!("Timed out reading the response from {authority}"));
+            }
+            Err(e) => return Err(format!("Failed to read response from {authority}: {e}")),
+        }
+    }
+    if response.len() as u64 > MAX_PAC_SIZE {
+        return Err(format!(
+            "PAC script from {authority} exceeds {MAX_PAC_SIZE} bytes"
+        ));
+    }
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| format!("Malformed response from {authority}"))?;
+    let status = head.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some("200") => Ok(body.to_string()),
+        _ => Err(format!("Unexpected response from {authority}: {status}")),
+    }
+}
+
+/// Run `task` on its own thread and wait for it until `deadline`. A task that
+/// is still blocked by then, e.g. in `getaddrinfo`, finishes in the background.
+fn before<T: Send + 'static>(
+    deadline: Instant,
+    task: impl FnOnce() -> T + Send + 'static,
+) -> Option<T> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(task());
+    });
+    rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .ok()
+}
+
+pub fn system_resolve(host: &str) -> Vec<IpAddr> {
+    if let Ok(ip) = host.parse() {
+        return vec![ip];
+    }
+    let mut addrs: Vec<_> = match (host, 0).to_socket_addrs() {
+        Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+        Err(_) => return Vec::new(),
+    };
+    addrs.sort_by_key(|addr| !addr.is_ipv4());
+    addrs.dedup();
+    addrs
+}
+
+/// The address of the interface used to reach `target`.
+fn local_address(bind: &str, target: &str) -> Option<IpAddr> {
+    // Connecting a UDP socket sends nothing, but selects the outgoing interface.
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect(target).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+fn my_ip_address() -> IpAddr {
+    local_address("0.0.0.0:0", "198.51.100.1:80").unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+/// The IPv4 and IPv6 addresses of the outgoing interfaces, for `myIpAddressEx`.
+fn my_ip_addresses() -> Vec<IpAddr> {
+    [
+        local_address("0.0.0.0:0", "198.51.100.1:80"),
+        local_address("[::]:0", "[2001:db8::1]:80"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Whether `ip` is inside a `10.0.0.0/8` or `fe80::/10` prefix.
+fn in_prefix(ip: IpAddr, prefix: &str) -> bool {
+    let Some((network, len)) = prefix.split_once('/') else {
+        return false;
+    };
+    match (network.parse(), len.parse()) {
+        (Ok(network), Ok(len)) => no_proxy::in_cidr(ip, network, len),
+        _ => false,
+    }
+}
+
+fn join_addresses(addrs: &[IpAddr]) -> String {
+    addrs
+        .iter()
+        .map(|it| it.to_string())
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn str_arg(args: &[Value], idx: usize) -> String {
+    args.get(idx).map(|it| it.to_str()).unwrap_or_default()
+}
+
+fn define_helpers(ctx: &mut Context, resolver: Resolver) {
+    ctx.define_native("isPlainHostName", |args| {
+        Value::Bool(!str_arg(args, 0).contains('.'))
+    });
+    ctx.define_native("dnsDomainIs", |args| {
+        let host = str_arg(args, 0).to_ascii_lowercase();
+        let domain = str_arg(args, 1).to_ascii_lowercase();
+        Value::Bool(host.ends_with(&domain))
+    });
+    ctx.define_native("localHostOrDomainIs", |args| {
+        let host = str_arg(args, 0).to_ascii_lowercase();
+        let hostdom = str_arg(args, 1).to_ascii_lowercase();
+        Value::Bool(
+            host == hostdom || (!host.contains('.') && hostdom.starts_with(&format!("{host}."))),
+        )
+    });
+    ctx.define_native("isResolvable", move |args| {
+        Value::Bool(!resolver(&str_arg(args, 0)).is_empty())
+    });
+    ctx.define_native("dnsResolve", move |args| {
+        match resolver(&str_arg(args, 0)).first() {
+            Some(ip) => Value::Str(ip.to_string()),
+            None => Value::Null,
+        }
+    });
+    ctx.define_native("isInNet", move |args| {
+        let ip = match resolver(&str_arg(args, 0)).first() {
+            Some(IpAddr::V4(ip)) => u32::from(*ip),
+            _ => return Value::Bool(false),
+        };
+        let pattern = str_arg(args, 1).parse::<Ipv4Addr>();
+        let mask = str_arg(args, 2).parse::<Ipv4Addr>();
+        match (pattern, mask) {
+            (Ok(pattern), Ok(mask)) => {
+                let mask = u32::from(mask);
+                Value::Bool(ip & mask == u32::from(pattern) & mask)
+            }
+            _ => Value::Bool(false),
+        }
+    });
+    ctx.define_native("myIpAddress", |_| Value::Str(my_ip_address().to_string()));
+    // Microsoft's extensions, which also cover IPv6 and list every address
+    ctx.define_native("isResolvableEx", move |args| {
+        Value::Bool(!resolver(&str_arg(args, 0)).is_empty())
+    });
+    ctx.define_native("dnsResolveEx", move |args| {
+        Value::Str(join_addresses(&resolver(&str_arg(args, 0))))
+    });
+    ctx.define_native("isInNetEx", move |args| {
+        let prefix = str_arg(args, 1);
+        let addrs = resolver(&str_arg(args, 0));
+        Value::Bool(addrs.into_iter().any(|ip| in_prefix(ip, &prefix)))
+    });
+    ctx.define_native("myIpAddressEx", |_| {
+        Value::Str(join_addresses(&my_ip_addresses()))
+    });
+    ctx.define_native("dnsDomainLevels", |args| {
+        Value::Num(str_arg(args, 0).matches('.').count() as f64)
+    });
+    ctx.define_native("shExpMatch", |args| {
+        Value::Bool(sh_exp_match(&str_arg(args, 0), &str_arg(args, 1)))
+    });
+    ctx.define_native("convert_addr", |args| {
+        match str_arg(args, 0).parse::<Ipv4Addr>() {
+            Ok(ip) => Value::Num(u32::from(ip) as f64),
+            Err(_) => Value::Num(0.0),
+        }
+    });
+    ctx.define_native("weekdayRange", |args| Value::Bool(weekday_range(args)));
+    ctx.define_native("dateRange", |args| Value::Bool(date_range(args)));
+    ctx.define_native("timeRange", |args| Value::Bool(time_range(args)));
+    ctx.define_native("alert", |_| Value::Undefined);
+}
+
+/// Shell-style glob matching with `*` and `?`.
+fn sh_exp_match(text: &str, pattern: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                t += 1;
+                p += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp + 1;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Current UTC time as (year, month 1-12, day, weekday 0=Sunday, seconds of day).
+///
+/// Time-based helpers are evaluated in UTC whether or not `"GMT"` is passed.
+fn now_utc() -> (i64, u32, u32, u32, u32) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    let days = secs.div_euclid(86400);
+    let secs_of_day = secs.rem_euclid(86400) as u32;
+    let weekday = (days + 4).rem_euclid(7) as u32;
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    (year, month, day, weekday, secs_of_day)
+}
+
+/// Drop the optional trailing `"GMT"` argument.
+fn without_gmt(args: &[Value]) -> &[Value] {
+    match args.last() {
+        Some(Value::Str(s)) if s == "GMT" => &args[..args.len() - 1],
+        _ => args,
+    }
+}
+
+fn in_range<T: PartialOrd>(value: T, start: T, end: T) -> bool {
+    if start <= end {
+        start <= value && value <= end
+    } else {
+        value >= start || value <= end
+    }
+}
+
+fn weekday_range(args: &[Value]) -> bool {
+    const DAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+    let day = |idx: usize| DAYS.iter().position(|d| *d == str_arg(args, idx));
+    let args = without_gmt(args);
+    let (_, _, _, today, _) = now_utc();
+    match (day(0), args.len()) {
+        (Some(start), 1) => today as usize == start,
+        (Some(start), 2) => match day(1) {
+            Some(end) => in_range(today as usize, start, end),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+fn date_range(args: &[Value]) -> bool {
+    const MONTHS: [&str; 12] = [
+        "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+    ];
+    let args = without_gmt(args);
+    let (year, month, day, _, _) = now_utc();
+
+    // Classify each argument as (kind, value): 0 = day, 1 = month, 2 = year.
+    let parts: Option<Vec<(u8, i64)>> = args
+        .iter()
+        .map(|arg| match arg {
+            Value::Str(s) => MONTHS
+                .iter()
+                .position(|m| m == s)
+                .map(|m| (1, m as i64 + 1)),
+            other => {
+                let n = other.to_num() as i64;
+                Some(if n > 31 { (2, n) } else { (0, n) })
+            }
+        })
+        .collect();
+    let parts = match parts {
+        Some(parts) if !parts.is_empty() => parts,
+        _ => return false,
+    };
+
+    let key = |parts: &[(u8, i64)]| {
+        let mut key = (None, None, None);
+        for &(kind, value) in parts {
+            match kind {
+                0 => key.2 = Some(value),
+                1 => key.1 = Some(value),
+                _ => key.0 = Some(value),
+            }
+        }
+        key
+    };
+    let today = |(y, m, d): (Option<i64>, Option<i64>, Option<i64>)| {
+        (
+            y.map(|_| year),
+            m.map(|_| month as i64),
+            d.map(|_| day as i64),
+        )
+    };
+
+    if parts.len() == 1 {
+        let single = key(&parts);
+        return today(single) == single;
+    }
+    if parts.len() % 2 != 0 {
+        return false;
+    }
+    let (start, end) = parts.split_at(parts.len() / 2);
+    let (start, end) = (key(start), key(end));
+    in_range(today(start), start, end)
+}
+
+fn time_range(args: &[Value]) -> bool {
+    let args = without_gmt(args);
+    let n: Vec<u32> = args.iter().map(|it| it.to_num() as u32).collect();
+    let (_, _, _, _, secs) = now_utc();
+    let hour = secs / 3600;
+    match n.as_slice() {
+        [h] => hour == *h,
+        [h1, h2] => in_range(hour, *h1, *h2),
+        [h1, m1, h2, m2] => in_range(secs / 60, h1 * 60 + m1, h2 * 60 + m2),
+        [h1, m1, s1, h2, m2, s2] => {
+            in_range(secs, h1 * 3600 + m1 * 60 + s1, h2 * 3600 + m2 * 60 + s2)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+        function FindProxyForURL(url, host) {
+            if (isPlainHostName(host) || dnsDomainIs(host, ".internal.example"))
+                return "DIRECT";
+            if (isInNet(host, "10.0.0.0", "255.0.0.0"))
+                return "DIRECT";
+            if (shExpMatch(url, "https://*"))
+                return "HTTPS secure.example:443; DIRECT";
+            if (url.substring(0, 4) == "tcp:")
+                return "SOCKS5 socks.example:1080";
+            return "PROXY proxy.example:8080; DIRECT";
+        }
+    "#;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn stub_resolve(host: &str) -> Vec<IpAddr> {
+        match host {
+            "intranet.lan" => vec![
+                IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3)),
+                "fd00::3".parse().unwrap(),
+            ],
+            _ => host.parse().into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn test_find_proxy() {
+        let mut pac = Pac::with_resolver(SCRIPT, stub_resolve, TIMEOUT).unwrap();
+        assert_eq!(
+            pac.find_proxy("http://google.com").unwrap(),
+            "http://proxy.example:8080"
+        );
+        assert_eq!(
+            pac.find_proxy("https://google.com").unwrap(),
+            "https://secure.example:443"
+        );
+        assert_eq!(
+            pac.find_proxy("tcp://google.com").unwrap(),
+            "socks5://socks.example:1080"
+        );
+        assert_eq!(pac.find_proxy("localhost").unwrap(), "");
+        assert_eq!(pac.find_proxy("http://a.internal.example/x").unwrap(), "");
+        assert_eq!(pac.find_proxy("http://intranet.lan").unwrap(), "");
+    }
+
+    #[test]
+    fn test_find_proxy_ex() {
+        let script = r#"
+            function FindProxyForURLEx(url, host) {
+                if (isInNetEx(host, "fd00::/8") && isInNetEx(host, "10.0.0.0/8"))
+                    return "DIRECT";
+                if (isResolvableEx(host))
+                    return "PROXY [" + dnsResolveEx(host) + "]:8080";
+                return "PROXY proxy.example:8080";
+            }
+        "#;
+        let mut pac = Pac::with_resolver(script, stub_resolve, TIMEOUT).unwrap();
+        assert_eq!(pac.find_proxy("http://intranet.lan").unwrap(), "");
+        assert_eq!(
+            pac.find_proxy("http://[fe80::1]/").unwrap(),
+            "http://[fe80::1]:8080"
+        );
+        assert_eq!(
+            pac.find_proxy("http://example.com").unwrap(),
+            "http://proxy.example:8080"
+        );
+    }
+
+    #[test]
+    fn test_parse_result() {
+        assert_eq!(parse_result("DIRECT").unwrap(), "");
+        assert_eq!(parse_result("").unwrap(), "");
+        assert_eq!(parse_result("PROXY p:1; DIRECT").unwrap(), "http://p:1");
+        assert_eq!(parse_result("SOCKS p:1").unwrap(), "socks://p:1");
+        assert!(parse_result("PROXY").is_err());
+        assert!(parse_result("BOGUS p:1").is_err());
+    }
+
+    #[test]
+    fn test_host_of() {
+        assert_eq!(host_of("http://google.com"), "google.com");
+        assert_eq!(
+            host_of("https://user:pw@example.com:8443/path"),
+            "example.com"
+        );
+        assert_eq!(host_of("http://[::1]:8080/"), "::1");
+        assert_eq!(host_of("localhost"), "localhost");
+    }
+
+    #[test]
+    fn test_sh_exp_match() {
+        assert!(sh_exp_match("www.example.com", "*.example.com"));
+        assert!(sh_exp_match("a.b", "?.?"));
+        assert!(!sh_exp_match("example.com", "*.example.com"));
+        assert!(sh_exp_match("anything", "*"));
+    }
+
+    #[test]
+    fn test_fetch() {
+        use std::net::TcpListener;
+
+        let err = fetch("https://wpad.example/proxy.pac", TIMEOUT).unwrap_err();
+        assert!(err.contains("HTTPS PAC URLs are not supported"));
+
+        // A server trickling its response is cut off by the overall deadline.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n");
+            while stream.write_all(b" ").is_ok() {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let start = Instant::now();
+        let url = format!("http://127.0.0.1:{port}/proxy.pac");
+        let err = fetch(&url, Duration::from_millis(300)).unwrap_err();
+        assert!(err.contains("Timed out"), "{err}");
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // An endless response stops at `MAX_PAC_SIZE`.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n");
+            while stream.write_all(&[b' '; 65536]).is_ok() {}
+        });
+        let url = format!("http://127.0.0.1:{port}/proxy.pac");
+        let err = fetch(&url, TIMEOUT).unwrap_err();
+        assert!(err.contains("exceeds"), "{err}");
+    }
+}
@@ -0,0 +1,196 @@
+//! Web Proxy Auto-Discovery (WPAD) over DNS: probe `wpad.<domain>` for each
+//! suffix of the search domains and download `/wpad.dat` from the first host
+//! that resolves.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use super::{before, http_get_from, system_resolve, Pac, Resolver};
+
+const WPAD_PATH: &str = "/wpad.dat";
+
+/// Discover and load a PAC script, giving up once `timeout` has elapsed.
+pub fn discover(search_domains: &[String], timeout: Duration) -> Result<Pac, String> {
+    let script = discover_with(search_domains, system_resolve, 80, timeout)?;
+    Pac::new(&script, timeout)
+}
+
+/// Find the WPAD script using `resolver` for host lookups and `port` for the
+/// HTTP request.
+pub(super) fn discover_with(
+    search_domains: &[String],
+    resolver: Resolver,
+    port: u16,
+    timeout: Duration,
+) -> Result<String, String> {
+    let deadline = Instant::now() + timeout;
+    let mut errors = Vec::new();
+
+    for host in candidates(search_domains) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            errors.push("timed out".to_string());
+            break;
+        }
+        let lookup = host.clone();
+        let ip = match before(deadline, move || resolver(&lookup).first().copied()) {
+            Some(Some(ip)) => ip,
+            Some(None) => continue,
+            None => {
+                errors.push(format!("timed out resolving {host}"));
+                break;
+            }
+        };
+        let authority = host.clone();
+        let fetch =
+            move || http_get_from(SocketAddr::new(ip, port), &authority, WPAD_PATH, remaining);
+        match before(deadline, fetch) {
+            Some(Ok(script)) => return Ok(script),
+            Some(Err(e)) => errors.push(e),
+            None => {
+                errors.push(format!("timed out fetching the script from {host}"));
+                break;
+            }
+        }
+    }
+
+    match errors.is_empty() {
+        true => Err("No WPAD host found".to_string()),
+        false => Err(format!("No WPAD script found: {}", errors.join("; "))),
+    }
+}
+
+/// `wpad.a.b.example.com`, `wpad.b.example.com`, `wpad.example.com` for the
+/// search domain `a.b.example.com`, never going up to the top-level domain.
+fn candidates(search_domains: &[String]) -> Vec<String> {
+    let mut hosts = Vec::new();
+    for domain in search_domains {
+        let labels: Vec<&str> = domain
+            .trim_matches('.')
+            .split('.')
+            .filter(|label| !label.is_empty())
+            .collect();
+        for start in 0..labels.len().saturating_sub(1) {
+            let host = format!("wpad.{}", labels[start..].join("."));
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+    }
+    hosts
+}
+
+/// Search domains configured in `/etc/resolv.conf`.
+#[cfg(unix)]
+pub fn system_search_domains() -> Vec<String> {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .map(|content| resolv_conf_domains(&content))
+        .unwrap_or_default()
+}
+
+/// Search domains from the `search` and `domain` lines of `resolv.conf`.
+#[cfg(unix)]
+fn resolv_conf_domains(content: &str) -> Vec<String> {
+    let mut domains = Vec::new();
+    for line in content.lines() {
+        let mut words = line.split_whitespace();
+        if let Some("search" | "domain") = words.next() {
+            for domain in words {
+                if !domains.iter().any(|it| it == domain) {
+                    domains.push(domain.to_string());
+                }
+            }
+        }
+    }
+    domains
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{IpAddr, Ipv4Addr, TcpListener};
+    use std::thread;
+
+    use super::*;
+
+    const SCRIPT: &str =
+        "function FindProxyForURL(url, host) { return \"PROXY wpad-proxy:3128\"; }";
+
+    fn stub_resolve(host: &str) -> Vec<IpAddr> {
+        match host {
+            "wpad.corp.example" => vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_candidates() {
+        assert_eq!(
+            candidates(&["a.corp.example".to_string(), "corp.example".to_string()]),
+            vec!["wpad.a.corp.example", "wpad.corp.example"]
+        );
+        assert!(candidates(&["localdomain".to_string()]).is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_resolv_conf_domains() {
+        let content =
+            "nameserver 10.0.0.1\ndomain corp.example\nsearch a.corp.example corp.example\n";
+        assert_eq!(
+            resolv_conf_domains(content),
+            vec!["corp.example", "a.corp.example"]
+        );
+    }
+
+    #[test]
+    fn test_discover() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with("\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                request.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+            write!(stream, "HTTP/1.0 200 OK\r\n\r\n{SCRIPT}").unwrap();
+            request
+        });
+
+        let domains = vec!["dept.corp.example".to_string()];
+        let script = discover_with(&domains, stub_resolve, port, Duration::from_secs(2)).unwrap();
+        assert_eq!(script, SCRIPT);
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /wpad.dat HTTP/1.0\r\n"));
+        assert!(request.contains("Host: wpad.corp.example\r\n"));
+
+        let mut pac = Pac::with_resolver(&script, stub_resolve, Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            pac.find_proxy("http://google.com").unwrap(),
+            "http://wpad-proxy:3128"
+        );
+    }
+
+    #[test]
+    fn test_discover_not_found() {
+        let domains = vec!["other.example".to_string()];
+        assert!(discover_with(&domains, stub_resolve, 1, Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn test_discover_slow_dns() {
+        fn slow_resolve(_: &str) -> Vec<IpAddr> {
+            thread::sleep(Duration::from_secs(5));
+            Vec::new()
+        }
+
+        let start = Instant::now();
+        let domains = vec!["corp.example".to_string()];
+        let result = discover_with(&domains, slow_resolve, 1, Duration::from_millis(200));
+        assert!(result.unwrap_err().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,823 @@
+//! A small forward proxy on localhost for `termproxy serve`. Each request goes
+//! either directly or through an upstream proxy, so the `no_proxy` rules are
+//! applied in one place rather than by every tool in its own way.
+//!
+//! Plain HTTP requests in absolute form (`GET http://host/path`), `CONNECT`
+//! tunnels and SOCKS5 `CONNECT` requests are accepted on the same port, told
+//! apart by their first byte. Upstreams may be HTTP proxies, which get the
+//! credentials of their URL as `Proxy-Authorization`, or SOCKS5 proxies;
+//! `https` proxies would need TLS and are not supported.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use colored::Colorize;
+
+use crate::{proxy_url::ProxyUrl, utils};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest wait for a head or handshake from either end. Tunnels have none,
+/// their connections may stay idle for long.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest request or response head accepted.
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Headers that only concern a single connection, which are never forwarded.
+const HOP_BY_HOP: [&str; 4] = [
+    "connection",
+    "keep-alive",
+    "proxy-authorization",
+    "proxy-connection",
+];
+
+/// SOCKS5 reply codes for a request that cannot be served.
+const SOCKS_FAILURE: u8 = 1;
+const SOCKS_COMMAND_UNSUPPORTED: u8 = 7;
+const SOCKS_ADDRESS_UNSUPPORTED: u8 = 8;
+
+/// Where a request goes, with `scheme` being `http` for plain requests and
+/// `https` for tunnels. SOCKS5 connections are guessed from their port, see
+/// `socks_scheme`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub scheme: &'static str,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Target {
+    /// `host:port`, bracketing IPv6 addresses.
+    pub fn authority(&self) -> String {
+        match self.host.contains(':') {
+            true => format!("[{}]:{}", self.host, self.port),
+            false => format!("{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// A parsed request head.
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    /// The request target as sent, e.g. `http://host/path` or `host:443`
+    uri: String,
+    version: String,
+    target: Target,
+    /// Path and query of plain requests
+    path: String,
+    headers: Vec<String>,
+}
+
+/// Serve connections from `listener` until accepting fails, each in its own
+/// thread. `route` picks the upstream for a request, `None` going direct.
+pub fn serve<F>(listener: TcpListener, route: F) -> io::Result<()>
+where
+    F: Fn(&Target) -> Option<ProxyUrl> + Send + Sync + 'static,
+{
+    let route = std::sync::Arc::new(route);
+    loop {
+        let (client, _) = listener.accept()?;
+        let route = route.clone();
+        std::thread::spawn(move || handle(client, &*route));
+    }
+}
+
+fn handle(client: TcpStream, route: &dyn Fn(&Target) -> Option<ProxyUrl>) {
+    let Ok(reader) = client.try_clone() else {
+        return;
+    };
+    let _ = client.set_read_timeout(Some(READ_TIMEOUT));
+    let mut reader = BufReader::new(reader);
+    // A SOCKS5 greeting starts with the version, an HTTP request with a method
+    if let Ok([5, ..]) = reader.fill_buf() {
+        return handle_socks(reader, &client, route);
+    }
+    let request = match read_head(&mut reader).and_then(|head| parse_request(&head)) {
+        Ok(request) => request,
+        Err(e) => return respond(&client, "400 Bad Request", &e),
+    };
+    let upstream = route(&request.target);
+    let result = match request.method.as_str() {
+        "CONNECT" => open_tunnel(&request.target, upstream.as_ref()).and_then(|server| {
+            (&client)
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .map_err(|e| e.to_string())?;
+            Ok(server)
+        }),
+        _ => forward(&request, upstream.as_ref()),
+    };
+    match result {
+        Ok(server) => tunnel(reader, &client, server),
+        Err(e) => respond(&client, "502 Bad Gateway", &utils::redact(&e)),
+    }
+}
+
+/// Lines up to the empty line ending an HTTP head, without line endings.
+fn read_head(reader: &mut impl BufRead) -> Result<Vec<String>, String> {
+    let mut head = Vec::new();
+    let mut size = 0;
+    loop {
+        let mut line = String::new();
+        // Limit the line too, a client must not make us buffer more than that
+        let read = reader
+            .by_ref()
+            .take((MAX_HEAD + 1 - size) as u64)
+            .read_line(&mut line)
+            .map_err(|e| format!("Failed to read the head: {e}"))?;
+        size += read;
+        if read == 0 {
+            return Err("Connection closed before the end of the head".to_string());
+        }
+        if size > MAX_HEAD {
+            return Err("Head too large".to_string());
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return Ok(head);
+        }
+        head.push(line.to_string());
+    }
+}
+
+fn parse_request(head: &[String]) -> Result<Request, String> {
+    let (line, headers) = head.split_first().ok_or("Empty request")?;
+    let [method, uri, version] = line.split(' ').collect::<Vec<_>>()[..] else {
+        return Err(format!("Invalid request line `{line}`"));
+    };
+    let (target, path) = match method {
+        "CONNECT" => (parse_authority(uri, "https", 443)?, String::new()),
+        _ => {
+            let rest = uri
+                .get(..7)
+                .filter(|it| it.eq_ignore_ascii_case("http://"))
+                .map(|_| &uri[7..])
+                .ok_or_else(|| format!("Only http:// URLs can be proxied, not `{uri}`"))?;
+            let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+            let path = match path.starts_with('?') {
+                true => format!("/{path}"),
+                false if path.is_empty() => "/".to_string(),
+                false => path.to_string(),
+            };
+            (parse_authority(authority, "http", 80)?, path)
+        }
+    };
+    Ok(Request {
+        method: method.to_string(),
+        uri: uri.to_string(),
+        version: version.to_string(),
+        target,
+        path,
+        headers: headers.to_vec(),
+    })
+}
+
+/// `host`, `host:port` or `[v6]:port`, without user info.
+fn parse_authority(value: &str, scheme: &'static str, default_port: u16) -> Result<Target, String> {
+    let invalid = || format!("Invalid host `{value}`");
+    let (host, port) = match value.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']').ok_or_else(invalid)? {
+            (host, "") => (host, None),
+            (host, port) => (host, Some(port.strip_prefix(':').ok_or_else(invalid)?)),
+        },
+        None => match value.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (value, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| invalid())?,
+        None => default_port,
+    };
+    if host.is_empty() || value.contains('@') {
+        return Err(invalid());
+    }
+    Ok(Target {
+        scheme,
+        host: host.to_ascii_lowercase(),
+        port,
+    })
+}
+
+fn respond(mut client: &TcpStream, status: &str, message: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{message}\n",
+        message.len() + 1
+    );
+    let _ = client.write_all(response.as_bytes());
+}
+
+fn connect(host: &str, port: u16) -> Result<TcpStream, String> {
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Failed to resolve {host}: {e}"))?;
+    let mut error = format!("No address found for {host}");
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                return Ok(stream);
+            }
+            Err(e) => error = format!("Failed to connect to {addr}: {e}"),
+        }
+    }
+    Err(error)
+}
+
+/// A connection to the target of a `CONNECT` request.
+fn open_tunnel(target: &Target, upstream: Option<&ProxyUrl>) -> Result<TcpStream, String> {
+    let Some(proxy) = upstream else {
+        return connect(&target.host, target.port);
+    };
+    match proxy.scheme.as_str() {
+        "http" => {
+            let mut server = connect(&proxy.host, proxy.port)?;
+            let authority = target.authority();
+            let head = format!(
+                "CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n{}\r\n",
+                proxy_authorization(proxy)
+            );
+            server
+                .write_all(head.as_bytes())
+                .map_err(|e| format!("Failed to write to {proxy}: {e}"))?;
+            // Read byte by byte, the tunneled data must stay in the socket
+            let head = read_head(&mut BufReader::with_capacity(1, &server))?;
+            let status = head.first().map_or("", String::as_str);
+            match status.split(' ').nth(1) {
+                Some(code) if code.starts_with('2') => Ok(server),
+                _ => Err(format!(
+                    "{proxy} refused to connect to {authority}: {status}"
+                )),
+            }
+        }
+        _ => connect_upstream(proxy, target),
+    }
+}
+
+/// A connection for a plain request with its head already sent.
+fn forward(request: &Request, upstream: Option<&ProxyUrl>) -> Result<TcpStream, String> {
+    let target = &request.target;
+    let (mut server, uri, authorization) = match upstream {
+        Some(proxy) if proxy.scheme == "http" => {
+            let server = connect(&proxy.host, proxy.port)?;
+            (server, request.uri.as_str(), proxy_authorization(proxy))
+        }
+        Some(proxy) => (
+            connect_upstream(proxy, target)?,
+            request.path.as_str(),
+            String::new(),
+        ),
+        None => (
+            connect(&target.host, target.port)?,
+            request.path.as_str(),
+            String::new(),
+        ),
+    };
+
+    let mut head = format!("{} {} {}\r\n", request.method, uri, request.version);
+    for header in &request.headers {
+        let name = header.split(':').next().unwrap_or_default().trim();
+        if !HOP_BY_HOP.iter().any(|it| name.eq_ignore_ascii_case(it)) {
+            head.push_str(header);
+            head.push_str("\r\n");
+        }
+    }
+    // One request per connection, so the next one is routed again
+    head.push_str(&authorization);
+    head.push_str("Connection: close\r\n\r\n");
+    server
+        .write_all(head.as_bytes())
+        .map_err(|e| format!("Failed to write to {}: {e}", target.authority()))?;
+    Ok(server)
+}
+
+/// `Proxy-Authorization` with the credentials of `proxy`, if it has any.
+fn proxy_authorization(proxy: &ProxyUrl) -> String {
+    match &proxy.credentials {
+        Some((user, password)) => format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            utils::base64_encode(format!("{user}:{password}").as_bytes())
+        ),
+        None => String::new(),
+    }
+}
+
+/// A connection to `target` through a SOCKS5 `proxy`. With `socks5h` the proxy
+/// resolves host names, otherwise they are resolved here.
+fn connect_upstream(proxy: &ProxyUrl, target: &Target) -> Result<TcpStream, String> {
+    if !matches!(proxy.scheme.as_str(), "socks" | "socks5" | "socks5h") {
+        return Err(format!("Unsupported upstream proxy {proxy}"));
+    }
+    let mut stream = connect(&proxy.host, proxy.port)?;
+    socks5_handshake(&mut stream, proxy, target).map_err(|e| {
+        format!(
+            "SOCKS5 proxy {proxy} failed to connect to {}: {e}",
+            target.authority()
+        )
+    })?;
+    Ok(stream)
+}
+
+fn socks5_handshake(
+    stream: &mut TcpStream,
+    proxy: &ProxyUrl,
+    target: &Target,
+) -> Result<(), String> {
+    let io = |e: io::Error| e.to_string();
+    let methods: &[u8] = match proxy.credentials {
+        Some(_) => &[0, 2],
+        None => &[0],
+    };
+    stream
+        .write_all(&[&[5, methods.len() as u8], methods].concat())
+        .map_err(io)?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).map_err(io)?;
+    match (reply, &proxy.credentials) {
+        ([5, 0], _) => {}
+        ([5, 2], Some((user, password))) => {
+            let (user, password) = (user.as_bytes(), password.as_bytes());
+            if user.len() > 255 || password.len() > 255 {
+                return Err("user name or password too long".to_string());
+            }
+            let mut auth = vec![1, user.len() as u8];
+            auth.extend(user);
+            auth.push(password.len() as u8);
+            auth.extend(password);
+            stream.write_all(&auth).map_err(io)?;
+            stream.read_exact(&mut reply).map_err(io)?;
+            if reply[1] != 0 {
+                return Err("authentication failed".to_string());
+            }
+        }
+        _ => return Err("no acceptable authentication method".to_string()),
+    }
+
+    let mut request = vec![5, 1, 0];
+    let ip = match target.host.parse::<IpAddr>() {
+        Ok(ip) => Some(ip),
+        Err(_) if proxy.scheme == "socks5h" => None,
+        Err(_) => Some(
+            (target.host.as_str(), target.port)
+                .to_socket_addrs()
+                .map_err(|e| format!("Failed to resolve {}: {e}", target.host))?
+                .next()
+                .ok_or_else(|| format!("No address found for {}", target.host))?
+                .ip(),
+        ),
+    };
+    match ip {
+        Some(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend(ip.octets());
+        }
+        Some(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend(ip.octets());
+        }
+        None => {
+            let host = target.host.as_bytes();
+            if host.len() > 255 {
+                return Err("host name too long".to_string());
+            }
+            request.extend([3, host.len() as u8]);
+            request.extend(host);
+        }
+    }
+    request.extend(target.port.to_be_bytes());
+    stream.write_all(&request).map_err(io)?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).map_err(io)?;
+    if reply[1] != 0 {
+        return Err(format!("request rejected with reply code {}", reply[1]));
+    }
+    // The bound address and port, which are of no use here
+    let len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0; 1];
+            stream.read_exact(&mut len).map_err(io)?;
+            len[0] as usize
+        }
+        atyp => return Err(format!("invalid address type {atyp}")),
+    };
+    stream.read_exact(&mut vec![0; len + 2]).map_err(io)?;
+    Ok(())
+}
+
+fn handle_socks(
+    mut reader: BufReader<TcpStream>,
+    client: &TcpStream,
+    route: &dyn Fn(&Target) -> Option<ProxyUrl>,
+) {
+    if !socks_greet(&mut reader, client) {
+        return;
+    }
+    let result = read_socks_request(&mut reader).and_then(|target| {
+        let upstream = route(&target);
+        open_tunnel(&target, upstream.as_ref()).map_err(|e| {
+            // SOCKS5 replies have no room for a message
+            eprintln!("{}: {}", "Warning".red(), utils::redact(&e));
+            SOCKS_FAILURE
+        })
+    });
+    match result {
+        Ok(server) => {
+            if socks_reply(client, 0).is_ok() {
+                tunnel(reader, client, server);
+            }
+        }
+        Err(code) => {
+            let _ = socks_reply(client, code);
+        }
+    }
+}
+
+/// Pick "no authentication" from the offered methods, which is all a proxy
+/// on localhost needs; clients that do not offer it are turned away.
+fn socks_greet(reader: &mut impl Read, mut client: &TcpStream) -> bool {
+    let mut greeting = [0; 2];
+    if reader.read_exact(&mut greeting).is_err() {
+        return false;
+    }
+    let mut methods = vec![0; greeting[1] as usize];
+    if reader.read_exact(&mut methods).is_err() {
+        return false;
+    }
+    let method = match methods.contains(&0) {
+        true => 0,
+        false => 0xff,
+    };
+    client.write_all(&[5, method]).is_ok() && method == 0
+}
+
+/// The target of a SOCKS5 `CONNECT` request, or the reply code to refuse it
+/// with. Host names are resolved later, when going direct or by the upstream.
+fn read_socks_request(reader: &mut impl Read) -> Result<Target, u8> {
+    let mut read = |len: usize| {
+        let mut buf = vec![0; len];
+        reader
+            .read_exact(&mut buf)
+            .map(|_| buf)
+            .map_err(|_| SOCKS_FAILURE)
+    };
+    let [5, command, 0, address_type] = read(4)?[..] else {
+        return Err(SOCKS_FAILURE);
+    };
+    let host = match address_type {
+        1 => Ipv4Addr::from(<[u8; 4]>::try_from(read(4)?).unwrap()).to_string(),
+        4 => Ipv6Addr::from(<[u8; 16]>::try_from(read(16)?).unwrap()).to_string(),
+        3 => {
+            let len = read(1)?[0] as usize;
+            String::from_utf8(read(len)?)
+                .ok()
+                .filter(|it| !it.is_empty() && !it.contains([':', '@', '/']))
+                .ok_or(SOCKS_ADDRESS_UNSUPPORTED)?
+        }
+        _ => return Err(SOCKS_ADDRESS_UNSUPPORTED),
+    };
+    let port = u16::from_be_bytes([read(1)?[0], read(1)?[0]]);
+    if command != 1 {
+        return Err(SOCKS_COMMAND_UNSUPPORTED);
+    }
+    Ok(Target {
+        scheme: socks_scheme(port),
+        host: host.to_ascii_lowercase(),
+        port,
+    })
+}
+
+/// The scheme a SOCKS5 connection is routed as. Only the port is known, so
+/// the well-known ones pick their proxy and every other connection is `tcp`.
+fn socks_scheme(port: u16) -> &'static str {
+    match port {
+        80 => "http",
+        443 => "https",
+        21 => "ftp",
+        _ => "tcp",
+    }
+}
+
+/// A reply with `code`. Clients have no use for the bound address, which is
+/// left empty.
+fn socks_reply(mut client: &TcpStream, code: u8) -> io::Result<()> {
+    client.write_all(&[5, code, 0, 1, 0, 0, 0, 0, 0, 0])
+}
+
+/// Copy between both ends until the server is done, including whatever the
+/// client sent past the head that is still buffered in `reader`.
+fn tunnel(mut reader: BufReader<TcpStream>, client: &TcpStream, server: TcpStream) {
+    let Ok(mut upload) = server.try_clone() else {
+        return;
+    };
+    let _ = client.set_read_timeout(None);
+    let _ = server.set_read_timeout(None);
+    let uploader = std::thread::spawn(move || {
+        let _ = io::copy(&mut reader, &mut upload);
+        let _ = upload.shutdown(Shutdown::Write);
+    });
+    let _ = io::copy(&mut &server, &mut &*client);
+    // Also ends the upload of a client that keeps the connection open
+    let _ = client.shutdown(Shutdown::Both);
+    let _ = server.shutdown(Shutdown::Both);
+    let _ = uploader.join();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::mpsc;
+
+    use super::*;
+
+    /// Start the proxy on a free port with a fixed route.
+    fn start(upstream: Option<ProxyUrl>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve(listener, move |_| upstream.clone()));
+        addr
+    }
+
+    /// A server that records the head of the first request, answers with
+    /// `response` and then echoes whatever else it receives, unless the
+    /// response closes the connection.
+    fn stand_in(response: &'static str) -> (SocketAddr, mpsc::Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            tx.send(read_head(&mut reader).unwrap()).unwrap();
+            (&stream).write_all(response.as_bytes()).unwrap();
+            if !response.contains("Connection: close") {
+                let _ = io::copy(&mut reader, &mut &stream);
+            }
+        });
+        (addr, rx)
+    }
+
+    fn request(proxy: SocketAddr, head: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(proxy).unwrap();
+        stream.write_all(head.as_bytes()).unwrap();
+        stream
+    }
+
+    fn read_all(mut stream: TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    /// Greet the proxy and send a SOCKS5 request, returning the reply code.
+    fn socks_request(proxy: SocketAddr, body: &[u8]) -> (TcpStream, u8) {
+        let mut stream = request(proxy, "");
+        stream.write_all(&[5, 1, 0]).unwrap();
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(reply, [5, 0]);
+        stream.write_all(body).unwrap();
+        let mut reply = [0; 10];
+        stream.read_exact(&mut reply).unwrap();
+        (stream, reply[1])
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello";
+
+    #[test]
+    fn test_read_head() {
+        let head = "GET http://example.com/ HTTP/1.1\r\nHost: example.com\r\n\r\nbody";
+        assert_eq!(
+            read_head(&mut head.as_bytes()),
+            Ok(vec![
+                "GET http://example.com/ HTTP/1.1".to_string(),
+                "Host: example.com".to_string()
+            ])
+        );
+        let line = vec![b'a'; 4 * MAX_HEAD];
+        let mut reader = line.as_slice();
+        assert_eq!(read_head(&mut reader), Err("Head too large".to_string()));
+        assert_eq!(reader.len(), 3 * MAX_HEAD - 1);
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let head = |line: &str| vec![line.to_string(), "Host: example.com".to_string()];
+        let request = parse_request(&head("GET http://Example.com:8080?q=1 HTTP/1.1")).unwrap();
+        assert_eq!(request.target.authority(), "example.com:8080");
+        assert_eq!(request.path, "/?q=1");
+        let request = parse_request(&head("CONNECT [::1]:443 HTTP/1.1")).unwrap();
+        assert_eq!(
+            request.target,
+            Target {
+                scheme: "https",
+                host: "::1".to_string(),
+                port: 443
+            }
+        );
+        assert!(parse_request(&head("GET /relative HTTP/1.1")).is_err());
+        assert!(parse_request(&head("GET https://example.com/ HTTP/1.1")).is_err());
+        assert!(parse_request(&head("CONNECT example.com:https HTTP/1.1")).is_err());
+        assert!(parse_request(&head("GET http://u:p@example.com/ HTTP/1.1")).is_err());
+    }
+
+    #[test]
+    fn test_forward_direct() {
+        let (origin, heads) = stand_in(OK);
+        let proxy = start(None);
+        let response = read_all(request(
+            proxy,
+            &format!(
+                "GET http://{origin}/path?q=1 HTTP/1.1\r\nHost: {origin}\r\nProxy-Connection: keep-alive\r\n\r\n"
+            ),
+        ));
+        assert!(response.ends_with("\r\n\r\nhello"), "{response}");
+        assert_eq!(
+            heads.recv().unwrap(),
+            [
+                "GET /path?q=1 HTTP/1.1".to_string(),
+                format!("Host: {origin}"),
+                "Connection: close".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_forward_upstream() {
+        let (upstream, heads) = stand_in(OK);
+        let upstream = format!("http://j%20doe:p%40ss@{upstream}").parse().ok();
+        let proxy = start(upstream);
+        let response = read_all(request(
+            proxy,
+            "GET http://example.com/ HTTP/1.1\r\nHost: example.com\r\nProxy-Authorization: Basic bm8=\r\n\r\n",
+        ));
+        assert!(response.ends_with("hello"), "{response}");
+        assert_eq!(
+            heads.recv().unwrap(),
+            [
+                "GET http://example.com/ HTTP/1.1",
+                "Host: example.com",
+                "Proxy-Authorization: Basic aiBkb2U6cEBzcw==",
+                "Connection: close"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_connect_direct() {
+        let (echo, heads) = stand_in("ready\n");
+        let proxy = start(None);
+        let mut stream = request(proxy, &format!("CONNECT {echo} HTTP/1.1\r\n\r\n"));
+        // The tunneled data itself is the head of the stand-in
+        stream.write_all(b"ping\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        assert_eq!(
+            read_head(&mut reader).unwrap(),
+            ["HTTP/1.1 200 Connection established"]
+        );
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "ready\n");
+        assert_eq!(heads.recv().unwrap(), ["ping"]);
+    }
+
+    #[test]
+    fn test_connect_upstream() {
+        let (upstream, heads) = stand_in("HTTP/1.1 200 OK\r\n\r\n");
+        let proxy = start(format!("http://user:pw@{upstream}").parse().ok());
+        let mut stream = request(proxy, "CONNECT git.corp.example:443 HTTP/1.1\r\n\r\n");
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        assert_eq!(
+            read_head(&mut reader).unwrap(),
+            ["HTTP/1.1 200 Connection established"]
+        );
+        assert_eq!(
+            heads.recv().unwrap(),
+            [
+                "CONNECT git.corp.example:443 HTTP/1.1",
+                "Host: git.corp.example:443",
+                "Proxy-Authorization: Basic dXNlcjpwdw=="
+            ]
+        );
+        // The stand-in echoes the tunneled data
+        stream.write_all(b"ping\n").unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "ping\n");
+
+        let (upstream, _heads) = stand_in("HTTP/1.1 407 Proxy Authentication Required\r\n\r\n");
+        let proxy = start(format!("http://{upstream}").parse().ok());
+        let response = read_all(request(proxy, "CONNECT example.com:443 HTTP/1.1\r\n\r\n"));
+        assert!(
+            response.starts_with("HTTP/1.1 502 Bad Gateway"),
+            "{response}"
+        );
+        assert!(response.contains("407"), "{response}");
+    }
+
+    #[test]
+    fn test_socks5_upstream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let socks = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0; 4];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 2, 0, 2]);
+            stream.write_all(&[5, 2]).unwrap();
+            let mut auth = [0; 7];
+            stream.read_exact(&mut auth).unwrap();
+            assert_eq!(&auth, b"\x01\x01u\x03p:w");
+            stream.write_all(&[1, 0]).unwrap();
+            let mut request = [0; 5 + 11 + 2];
+            stream.read_exact(&mut request).unwrap();
+            tx.send(request.to_vec()).unwrap();
+            stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+            read_head(&mut BufReader::new(&stream)).unwrap();
+            stream.write_all(OK.as_bytes()).unwrap();
+        });
+
+        let proxy = start(format!("socks5h://u:p%3Aw@{socks}").parse().ok());
+        let response = read_all(request(
+            proxy,
+            "GET http://example.com/ HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        ));
+        assert!(response.ends_with("hello"), "{response}");
+        assert_eq!(
+            rx.recv().unwrap(),
+            b"\x05\x01\x00\x03\x0bexample.com\x00\x50"
+        );
+    }
+
+    #[test]
+    fn test_socks_direct() {
+        let (echo, heads) = stand_in("ready\n");
+        let proxy = start(None);
+        let mut request = vec![5, 1, 0, 1, 127, 0, 0, 1];
+        request.extend(echo.port().to_be_bytes());
+        let (mut stream, code) = socks_request(proxy, &request);
+        assert_eq!(code, 0);
+        stream.write_all(b"ping\r\n\r\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        assert_eq!(line, "ready\n");
+        assert_eq!(heads.recv().unwrap(), ["ping"]);
+    }
+
+    #[test]
+    fn test_socks_upstream() {
+        let (upstream, heads) = stand_in("HTTP/1.1 200 OK\r\n\r\n");
+        let upstream: Option<ProxyUrl> = format!("http://{upstream}").parse().ok();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let (tx, targets) = mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        std::thread::spawn(move || {
+            serve(listener, move |target| {
+                tx.lock().unwrap().send(target.clone()).unwrap();
+                upstream.clone()
+            })
+        });
+
+        let (mut stream, code) = socks_request(proxy, b"\x05\x01\x00\x03\x0bGit.Example\x00\x16");
+        assert_eq!(code, 0);
+        assert_eq!(
+            targets.recv().unwrap(),
+            Target {
+                scheme: "tcp",
+                host: "git.example".to_string(),
+                port: 22
+            }
+        );
+        assert_eq!(
+            heads.recv().unwrap(),
+            ["CONNECT git.example:22 HTTP/1.1", "Host: git.example:22"]
+        );
+        stream.write_all(b"ping\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        assert_eq!(line, "ping\n");
+    }
+
+    #[test]
+    fn test_socks_refused() {
+        let proxy = start(None);
+        // BIND
+        let (_, code) = socks_request(proxy, &[5, 2, 0, 1, 127, 0, 0, 1, 0, 80]);
+        assert_eq!(code, SOCKS_COMMAND_UNSUPPORTED);
+        let (_, code) = socks_request(proxy, b"\x05\x01\x00\x03\x03a:b\x00\x50");
+        assert_eq!(code, SOCKS_ADDRESS_UNSUPPORTED);
+
+        // Only user name and password authentication
+        let mut stream = request(proxy, "");
+        stream.write_all(&[5, 1, 2]).unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+        assert_eq!(reply, [5, 0xff]);
+    }
+}
@@ -0,0 +1,251 @@
+//! Read-only access to the dconf databases backing GSettings, so the GNOME
+//! settings are available without a session bus.
+//!
+//! The databases are GVDB files: hash tables whose items point to serialized
+//! GVariant values. Only the value types used by the proxy schemas are
+//! decoded.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i32),
+    Str(String),
+    StrList(Vec<String>),
+}
+
+/// Keys and values of one database file, e.g. `/system/proxy/mode`.
+#[derive(Debug, Default)]
+pub struct Database(Vec<(String, Value)>);
+
+const SIGNATURE: &[u8; 8] = b"GVariant";
+const ITEM_SIZE: usize = 24;
+const NO_PARENT: u32 = u32::MAX;
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// The part of `data` a pointer at `offset` of `pointer` refers to.
+fn region<'a>(data: &'a [u8], pointer: &[u8], offset: usize) -> Option<&'a [u8]> {
+    let start = u32_at(pointer, offset)? as usize;
+    let end = u32_at(pointer, offset + 4)? as usize;
+    data.get(start..end)
+}
+
+impl Database {
+    pub fn parse(data: &[u8]) -> Result<Database, String> {
+        Database::parse_items(data).ok_or_else(|| "Invalid dconf database".to_string())
+    }
+
+    fn parse_items(data: &[u8]) -> Option<Database> {
+        // Databases are written in native byte order, only little-endian is supported
+        if data.get(..8)? != SIGNATURE {
+            return None;
+        }
+        let table = region(data, data, 16)?;
+        let n_bloom_words = (u32_at(table, 0)? & ((1 << 27) - 1)) as usize;
+        let n_buckets = u32_at(table, 4)? as usize;
+        let items = table.get(8 + 4 * (n_bloom_words + n_buckets)..)?;
+
+        struct Item<'a> {
+            parent: u32,
+            name: &'a [u8],
+            value: Option<&'a [u8]>,
+        }
+        let items = items
+            .chunks_exact(ITEM_SIZE)
+            .map(|item| {
+                let key_start = u32_at(item, 8)? as usize;
+                let key_size = u16::from_le_bytes([item[12], item[13]]) as usize;
+                Some(Item {
+                    parent: u32_at(item, 4)?,
+                    name: data.get(key_start..key_start + key_size)?,
+                    value: (item[14] == b'v').then(|| region(data, item, 16)).flatten(),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut values = Vec::new();
+        for item in &items {
+            let Some(value) = item.value.and_then(parse_variant) else {
+                continue;
+            };
+            // Item names are relative to their parent directory
+            let mut key = item.name.to_vec();
+            let mut parent = item.parent;
+            for _ in 0..items.len() {
+                if parent == NO_PARENT {
+                    break;
+                }
+                let dir = items.get(parent as usize)?;
+                key.splice(0..0, dir.name.iter().copied());
+                parent = dir.parent;
+            }
+            values.push((String::from_utf8(key).ok()?, value));
+        }
+        Some(Database(values))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.0
+            .iter()
+            .find(|(it, _)| it == key)
+            .map(|(_, value)| value)
+    }
+}
+
+/// A serialized variant: the value, a zero byte, then its type string.
+fn parse_variant(data: &[u8]) -> Option<Value> {
+    let separator = data.iter().rposition(|&it| it == 0)?;
+    let (value, value_type) = (&data[..separator], &data[separator + 1..]);
+    match value_type {
+        b"b" => Some(Value::Bool(*value.first()? != 0)),
+        b"i" => Some(Value::Int(i32::from_le_bytes(
+            value.get(..4)?.try_into().ok()?,
+        ))),
+        b"s" => parse_string(value).map(Value::Str),
+        b"as" => parse_string_array(value).map(Value::StrList),
+        _ => None,
+    }
+}
+
+fn parse_string(data: &[u8]) -> Option<String> {
+    let (0, text) = data.split_last()? else {
+        return None;
+    };
+    String::from_utf8(text.to_vec()).ok()
+}
+
+/// Variable-size elements followed by the offsets of their ends, each as wide
+/// as the smallest integer that can address the whole array.
+fn parse_string_array(data: &[u8]) -> Option<Vec<String>> {
+    if data.is_empty() {
+        return Some(Vec::new());
+    }
+    let offset_size = match data.len() {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        _ => 4,
+    };
+    let offset = |at: usize| -> Option<usize> {
+        let bytes = data.get(at..at + offset_size)?;
+        Some(
+            bytes
+                .iter()
+                .rev()
+                .fold(0, |acc, &byte| (acc << 8) | byte as usize),
+        )
+    };
+
+    let offsets_start = offset(data.len() - offset_size)?;
+    let offsets = data.get(offsets_start..)?;
+    if offsets.len() % offset_size != 0 {
+        return None;
+    }
+    let mut start = 0;
+    let mut strings = Vec::new();
+    for at in (offsets_start..data.len()).step_by(offset_size) {
+        let end = offset(at)?;
+        strings.push(parse_string(data.get(start..end)?)?);
+        start = end;
+    }
+    Some(strings)
+}
+
+/// Database files in lookup order, as listed by the dconf profile
+/// (`$DCONF_PROFILE` or `/etc/dconf/profile/user`), defaulting to the user
+/// database alone.
+fn database_paths() -> Vec<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|it| !it.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    let user_db = |name: &str| config_dir.as_ref().map(|dir| dir.join("dconf").join(name));
+
+    let profile = match std::env::var("DCONF_PROFILE") {
+        Ok(name) if name.starts_with('/') => PathBuf::from(name),
+        Ok(name) => Path::new("/etc/dconf/profile").join(name),
+        Err(_) => PathBuf::from("/etc/dconf/profile/user"),
+    };
+    let Ok(profile) = std::fs::read_to_string(profile) else {
+        return user_db("user").into_iter().collect();
+    };
+
+    profile
+        .lines()
+        .filter_map(|line| match line.trim().split_once(':')? {
+            ("user-db", name) => user_db(name),
+            ("system-db", name) => Some(Path::new("/etc/dconf/db").join(name)),
+            ("file-db", path) => Some(PathBuf::from(path)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The databases of the current dconf profile that exist, in lookup order.
+pub fn load() -> Result<Vec<Database>, String> {
+    let mut databases = Vec::new();
+    for path in database_paths() {
+        match std::fs::read(&path) {
+            Ok(data) => databases
+                .push(Database::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+    Ok(databases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_DB: &[u8] = include_bytes!("../../tests/fixtures/dconf-user");
+
+    #[test]
+    fn test_parse_database() {
+        let db = Database::parse(USER_DB).unwrap();
+        assert_eq!(
+            db.get("/system/proxy/mode"),
+            Some(&Value::Str("manual".to_string()))
+        );
+        assert_eq!(db.get("/system/proxy/http/port"), Some(&Value::Int(3128)));
+        assert_eq!(
+            db.get("/system/proxy/http/use-authentication"),
+            Some(&Value::Bool(true))
+        );
+        assert_eq!(
+            db.get("/system/proxy/use-same-proxy"),
+            Some(&Value::Bool(false))
+        );
+        assert_eq!(
+            db.get("/system/proxy/ignore-hosts"),
+            Some(&Value::StrList(vec![
+                "localhost".to_string(),
+                "127.0.0.0/8".to_string(),
+                "::1".to_string(),
+                "*.corp.example".to_string(),
+            ]))
+        );
+        assert_eq!(
+            db.get("/org/gnome/desktop/interface/clock-format"),
+            Some(&Value::Str("12h".to_string()))
+        );
+        assert_eq!(db.get("/system/proxy/ftp/host"), None);
+
+        assert!(Database::parse(b"not a database").is_err());
+    }
+
+    #[test]
+    fn test_parse_string_array() {
+        assert_eq!(parse_string_array(b""), Some(Vec::new()));
+        assert_eq!(
+            parse_string_array(b"a\0bc\0\x02\x05"),
+            Some(vec!["a".to_string(), "bc".to_string()])
+        );
+        assert_eq!(parse_string_array(b"a\0\x09"), None);
+    }
+}
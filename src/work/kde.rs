@@ -0,0 +1,238 @@
+//! KDE Plasma proxy settings from the `[Proxy Settings]` group of
+//! `kioslaverc`, for when the desktop portal is not available.
+
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+use colored::Colorize;
+
+use super::{linux::ignore_hosts_to_no_proxy, ProxyList, RawList};
+use crate::proxy_url::ProxyUrl;
+
+/// The `ProxyType` setting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    None,
+    Manual,
+    /// `Proxy Config Script`
+    Pac(String),
+    Wpad,
+    /// The proxy settings name environment variables holding the values
+    Env,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KdeProxy {
+    pub mode: Mode,
+    http: String,
+    https: String,
+    ftp: String,
+    socks: String,
+    no_proxy: String,
+    /// `NoProxyFor` lists the only hosts to use the proxies for
+    reversed_exception: bool,
+}
+
+/// `$XDG_CONFIG_HOME/kioslaverc`, falling back to `~/.config`.
+fn path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|it| !it.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("kioslaverc"))
+}
+
+/// The settings from `kioslaverc`, if there is one.
+pub fn load() -> Result<Option<KdeProxy>, String> {
+    let Some(path) = path() else {
+        return Ok(None);
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(Some(parse(&content))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+pub fn parse(content: &str) -> KdeProxy {
+    let mut settings = Vec::new();
+    let mut in_group = false;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            in_group = line == "[Proxy Settings]";
+        } else if let (true, Some((key, value))) = (in_group, line.split_once('=')) {
+            // Drop flags and locales, e.g. `httpProxy[$e]`
+            let key = key.split('[').next().unwrap_or_default().trim();
+            settings.push((key, value.trim()));
+        }
+    }
+    let get = |name: &str| {
+        settings
+            .iter()
+            .rev()
+            .find(|(key, _)| *key == name)
+            .map_or("", |(_, value)| *value)
+            .to_string()
+    };
+
+    KdeProxy {
+        mode: match get("ProxyType").as_str() {
+            "1" => Mode::Manual,
+            "2" => Mode::Pac(get("Proxy Config Script")),
+            "3" => Mode::Wpad,
+            "4" => Mode::Env,
+            _ => Mode::None,
+        },
+        http: get("httpProxy"),
+        https: get("httpsProxy"),
+        ftp: get("ftpProxy"),
+        socks: get("socksProxy"),
+        no_proxy: get("NoProxyFor"),
+        reversed_exception: get("ReversedException") == "true",
+    }
+}
+
+/// KDE writes `http://host 8080` with the port separated by a space, and
+/// `socks://` for SOCKS5.
+fn normalize_url(value: &str, default_scheme: &str) -> Result<Option<ProxyUrl>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let value = match value.split_once(' ') {
+        Some((url, port)) => format!("{}:{}", url.trim_end_matches('/'), port.trim()),
+        None => value.to_string(),
+    };
+    let value = match value.split_once("://") {
+        Some(("socks", rest)) => format!("socks5://{rest}"),
+        _ => value,
+    };
+    ProxyUrl::with_default_scheme(&value, default_scheme)
+        .map(Some)
+        .map_err(|e| format!("Invalid proxy `{value}`: {e}"))
+}
+
+impl KdeProxy {
+    /// The manual or environment proxies, looking variables up with `env`;
+    /// other modes have none.
+    pub fn to_proxies(
+        &self,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<ProxyList<'static>, String> {
+        let resolve = |value: &str| -> String {
+            match self.mode {
+                Mode::Manual => value.to_string(),
+                // Either a single name or a comma separated list of names
+                Mode::Env => value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .find_map(|name| env(name).filter(|it| !it.is_empty()))
+                    .unwrap_or_default(),
+                _ => String::new(),
+            }
+        };
+
+        let mut no_proxy = resolve(&self.no_proxy);
+        if self.reversed_exception && !no_proxy.is_empty() {
+            eprintln!(
+                "{}: KDE proxy exceptions are reversed, which no_proxy cannot express, ignoring them",
+                "Warning".red()
+            );
+            no_proxy.clear();
+        }
+        let hosts: Vec<String> = no_proxy
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(String::from)
+            .collect();
+
+        Ok(ProxyList {
+            http: normalize_url(&resolve(&self.http), "http")?,
+            https: normalize_url(&resolve(&self.https), "http")?,
+            ftp: normalize_url(&resolve(&self.ftp), "http")?,
+            all: normalize_url(&resolve(&self.socks), "socks5")?,
+            no: Cow::Owned(ignore_hosts_to_no_proxy(&hosts)),
+        })
+    }
+
+    /// The settings each proxy was read from, e.g. `httpProxy=http://proxy 8080`.
+    pub fn raw(&self) -> RawList {
+        let raw = |key: &str, value: &str| match value.is_empty() {
+            true => String::new(),
+            false => format!("{key}={value}"),
+        };
+        RawList {
+            http: raw("httpProxy", &self.http),
+            https: raw("httpsProxy", &self.https),
+            ftp: raw("ftpProxy", &self.ftp),
+            all: raw("socksProxy", &self.socks),
+            no: raw("NoProxyFor", &self.no_proxy),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn test_manual() {
+        let kde = parse(include_str!("../../tests/fixtures/kioslaverc-manual"));
+        assert_eq!(kde.mode, Mode::Manual);
+        assert_eq!(
+            kde.to_proxies(no_env),
+            Ok(ProxyList {
+                http: "http://proxy.corp.example:3128".parse().ok(),
+                https: "http://proxy.corp.example:3129".parse().ok(),
+                ftp: None,
+                all: "socks5://socks.corp.example:1080".parse().ok(),
+                no: Cow::Borrowed("localhost,127.0.0.1,.corp.example,.lan"),
+            })
+        );
+
+        let reversed = parse(
+            "[Proxy Settings]\nProxyType=1\nhttpProxy=proxy:3128\nNoProxyFor=only.example\nReversedException=true\n",
+        );
+        let proxies = reversed.to_proxies(no_env).unwrap();
+        assert_eq!(proxies.http, "http://proxy:3128".parse().ok());
+        assert_eq!(proxies.no, "");
+
+        let invalid = parse("[Proxy Settings]\nProxyType=1\nhttpProxy=http://pro xy 3128\n");
+        assert!(invalid.to_proxies(no_env).is_err());
+    }
+
+    #[test]
+    fn test_pac() {
+        let kde = parse(include_str!("../../tests/fixtures/kioslaverc-pac"));
+        assert_eq!(
+            kde.mode,
+            Mode::Pac("http://wpad.corp.example/proxy.pac".to_string())
+        );
+        assert_eq!(kde.to_proxies(no_env), Ok(ProxyList::default()));
+    }
+
+    #[test]
+    fn test_env() {
+        let kde = parse(include_str!("../../tests/fixtures/kioslaverc-env"));
+        assert_eq!(kde.mode, Mode::Env);
+        let env = |name: &str| match name {
+            "HTTP_PROXY" => Some("http://env-proxy:8080".to_string()),
+            "NO_PROXY" => Some("localhost,*.internal".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            kde.to_proxies(env),
+            Ok(ProxyList {
+                http: "http://env-proxy:8080".parse().ok(),
+                no: Cow::Borrowed("localhost,.internal"),
+                ..Default::default()
+            })
+        );
+    }
+}
@@ -1,149 +1,228 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::process::Command;
 
-use super::ProxyList;
-use crate::utils::Terminal;
-
-pub fn get_proxies(terminal: Terminal) -> ProxyList<'static> {
-    let mut proxies = ProxyList::default();
-
-    // Get HTTP proxy
-    if let Some(http_proxy) = get_proxy_for_protocol("http") {
-        proxies.http = Cow::Owned(http_proxy);
-    }
-
-    // Get HTTPS proxy
-    if let Some(https_proxy) = get_proxy_for_protocol("https") {
-        proxies.https = Cow::Owned(https_proxy);
+use colored::Colorize;
+
+use super::{Detection, ProxyList, RawList};
+use crate::{
+    cli::QueryOptions, network::NetworkInfo, no_proxy, pac::wpad, proxy_url::ProxyUrl, utils,
+};
+
+pub fn get_proxies(query_options: &QueryOptions) -> Detection {
+    let scutil = Scutil::load();
+    let result = if let Some(url) = get_proxy_auto_config_url(&scutil) {
+        Some(super::from_pac(&url, query_options))
+    } else if scutil.enabled("ProxyAutoDiscoveryEnable") {
+        Some(super::from_wpad(
+            &wpad::system_search_domains(),
+            query_options,
+        ))
+    } else {
+        None
+    };
+    match result {
+        Some(Ok(detection)) => return detection,
+        Some(Err(e)) => eprintln!(
+            "{}: Automatic proxy configuration failed: {}",
+            "Warning".red(),
+            utils::redact(&e)
+        ),
+        None => {}
     }
 
-    // Get FTP proxy
-    if let Some(ftp_proxy) = get_proxy_for_protocol("ftp") {
-        proxies.ftp = Cow::Owned(ftp_proxy);
-    }
+    let mut proxies = ProxyList::default();
 
-    // Get SOCKS proxy (for all_proxy)
-    if let Some(socks_proxy) = get_proxy_for_protocol("socks") {
-        proxies.all = Cow::Owned(socks_proxy);
-    }
+    proxies.http = parse_proxy(get_proxy_for_protocol(&scutil, "http"));
+    proxies.https = parse_proxy(get_proxy_for_protocol(&scutil, "https"));
+    proxies.ftp = parse_proxy(get_proxy_for_protocol(&scutil, "ftp"));
+    // SOCKS proxy (for all_proxy)
+    proxies.all = parse_proxy(get_proxy_for_protocol(&scutil, "socks"));
 
     // Get proxy bypass list
-    if let Some(no_proxy) = get_proxy_bypass_list() {
+    if let Some(no_proxy) = get_proxy_bypass_list(&scutil) {
         proxies.no = Cow::Owned(no_proxy);
     }
 
-    proxies
+    Detection {
+        raw: scutil_raw(&scutil, &proxies),
+        proxies,
+        lookup: None,
+    }
 }
 
-fn get_proxy_for_protocol(protocol: &str) -> Option<String> {
-    let output = Command::new("scutil").arg("--proxy").output().ok()?;
+/// The top-level keys of the `scutil --proxy` dictionary, read once per
+/// detection.
+#[derive(Default)]
+struct Scutil {
+    values: HashMap<String, String>,
+    arrays: HashMap<String, Vec<String>>,
+}
 
-    let proxy_info = String::from_utf8(output.stdout).ok()?;
+impl Scutil {
+    fn load() -> Self {
+        Command::new("scutil")
+            .arg("--proxy")
+            .output()
+            .ok()
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|text| Self::parse(&text))
+            .unwrap_or_default()
+    }
 
-    match protocol {
-        "http" => {
-            if proxy_info.contains("HTTPEnable : 1") {
-                let host = extract_value(&proxy_info, "HTTPProxy")?;
-                let port = extract_value(&proxy_info, "HTTPPort")?;
-                Some(format!("http://{}:{}", host, port))
-            } else {
-                None
-            }
-        }
-        "https" => {
-            if proxy_info.contains("HTTPSEnable : 1") {
-                let host = extract_value(&proxy_info, "HTTPSProxy")?;
-                let port = extract_value(&proxy_info, "HTTPSPort")?;
-                Some(format!("https://{}:{}", host, port))
-            } else {
-                None
-            }
-        }
-        "ftp" => {
-            if proxy_info.contains("FTPEnable : 1") {
-                let host = extract_value(&proxy_info, "FTPProxy")?;
-                let port = extract_value(&proxy_info, "FTPPort")?;
-                Some(format!("ftp://{}:{}", host, port))
-            } else {
-                None
-            }
-        }
-        "socks" => {
-            if proxy_info.contains("SOCKSEnable : 1") {
-                let host = extract_value(&proxy_info, "SOCKSProxy")?;
-                let port = extract_value(&proxy_info, "SOCKSPort")?;
-                Some(format!("socks://{}:{}", host, port))
-            } else {
-                None
+    /// Parse `Key : value` lines and `Key : <array> { 0 : item }` blocks,
+    /// skipping nested dictionaries such as `__SCOPED__`.
+    fn parse(text: &str) -> Self {
+        let mut scutil = Self::default();
+        let mut depth = 0;
+        let mut array: Option<(String, Vec<String>)> = None;
+        for line in text.lines().map(str::trim) {
+            let (key, value) = match line.split_once(" : ") {
+                Some((key, value)) => (Some(key), value.trim()),
+                None => (None, line),
+            };
+            if value.ends_with('{') {
+                depth += 1;
+                if let (2, Some(key), true) = (depth, key, value.starts_with("<array>")) {
+                    array = Some((key.to_string(), Vec::new()));
+                }
+            } else if value == "}" {
+                if depth == 2 {
+                    if let Some((key, items)) = array.take() {
+                        scutil.arrays.insert(key, items);
+                    }
+                }
+                depth -= 1;
+            } else if let (2, Some((_, items))) = (depth, &mut array) {
+                items.push(value.trim_matches('"').to_string());
+            } else if let (1, Some(key)) = (depth, key) {
+                scutil
+                    .values
+                    .insert(key.to_string(), value.trim_matches('"').to_string());
             }
         }
-        _ => None,
+        scutil
     }
-}
-
-fn get_proxy_bypass_list() -> Option<String> {
-    let output = Command::new("scutil").arg("--proxy").output().ok()?;
 
-    let proxy_info = String::from_utf8(output.stdout).ok()?;
+    fn value(&self, key: &str) -> Option<&str> {
+        self.values
+            .get(key)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
 
-    // Look for ProxyAutoConfigEnable or ExceptionsList
-    if let Some(exceptions) = extract_array_value(&proxy_info, "ExceptionsList") {
-        Some(exceptions.join(","))
-    } else {
-        None
+    fn enabled(&self, key: &str) -> bool {
+        self.value(key) == Some("1")
     }
 }
 
-fn extract_value(text: &str, key: &str) -> Option<String> {
-    let pattern = format!("{} : ", key);
-    let start = text.find(&pattern)? + pattern.len();
-    let end = text[start..].find('\n').unwrap_or(text.len() - start) + start;
-    let value = text[start..end].trim();
-    if value.is_empty() {
-        None
-    } else {
-        Some(value.to_string())
+/// A proxy built from the `scutil --proxy` keys, dropped with a warning if
+/// the host or port is invalid.
+fn parse_proxy(proxy: Option<Result<ProxyUrl, String>>) -> Option<ProxyUrl> {
+    match proxy? {
+        Ok(url) => Some(url),
+        Err(e) => {
+            eprintln!("{}: {}", "Warning".red(), utils::redact(&e));
+            None
+        }
     }
 }
 
-fn extract_array_value(text: &str, key: &str) -> Option<Vec<String>> {
-    let pattern = format!("{} : {{", key);
-    let start = text.find(&pattern)?;
-    let array_start = start + pattern.len();
-
-    // Find the closing brace
-    let mut brace_count = 1;
-    let mut end = array_start;
-    let chars: Vec<char> = text[array_start..].chars().collect();
-
-    for (i, &ch) in chars.iter().enumerate() {
-        match ch {
-            '{' => brace_count += 1,
-            '}' => {
-                brace_count -= 1;
-                if brace_count == 0 {
-                    end = array_start + i;
-                    break;
-                }
-            }
-            _ => {}
-        }
+/// The `scutil --proxy` keys behind each proxy, e.g. `HTTPProxy : proxy, HTTPPort : 8080`.
+fn scutil_raw(scutil: &Scutil, proxies: &ProxyList) -> RawList {
+    let key = |name: String| {
+        let value = scutil.value(&name).unwrap_or_default();
+        format!("{name} : {value}")
+    };
+    let raw = |value: &Option<ProxyUrl>, prefix: &str| match value {
+        None => String::new(),
+        Some(_) => format!(
+            "{}, {}",
+            key(format!("{prefix}Proxy")),
+            key(format!("{prefix}Port"))
+        ),
+    };
+    RawList {
+        http: raw(&proxies.http, "HTTP"),
+        https: raw(&proxies.https, "HTTPS"),
+        ftp: raw(&proxies.ftp, "FTP"),
+        all: raw(&proxies.all, "SOCKS"),
+        no: match proxies.no.is_empty() {
+            true => String::new(),
+            false => format!("ExceptionsList : {}", proxies.no),
+        },
     }
+}
 
-    let array_content = &text[array_start..end];
-    let mut items = Vec::new();
+pub fn network_info() -> NetworkInfo {
+    let command_output = |program: &str, args: &[&str]| {
+        Command::new(program)
+            .args(args)
+            .output()
+            .ok()
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .unwrap_or_default()
+    };
+
+    let gateways = command_output("route", &["-n", "get", "default"])
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("gateway:"))
+        .filter_map(|gateway| gateway.trim().parse().ok())
+        .collect();
+    let ssids = command_output("networksetup", &["-getairportnetwork", "en0"])
+        .lines()
+        .filter_map(|line| line.strip_prefix("Current Wi-Fi Network: "))
+        .map(|ssid| ssid.trim().to_string())
+        .collect();
+    let interfaces = command_output("ifconfig", &["-lu"])
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+
+    NetworkInfo {
+        gateways,
+        search_domains: wpad::system_search_domains(),
+        ssids,
+        interfaces,
+    }
+}
 
-    for line in array_content.lines() {
-        let line = line.trim();
-        if line.starts_with('"') && line.ends_with('"') {
-            let item = &line[1..line.len() - 1];
-            items.push(item.to_string());
-        }
+/// The proxy for `protocol` if it is enabled. The HTTPS proxy is a plain HTTP
+/// proxy that tunnels with `CONNECT`, not one spoken to over TLS.
+fn get_proxy_for_protocol(scutil: &Scutil, protocol: &str) -> Option<Result<ProxyUrl, String>> {
+    let (prefix, scheme) = match protocol {
+        "http" => ("HTTP", "http"),
+        "https" => ("HTTPS", "http"),
+        "ftp" => ("FTP", "ftp"),
+        "socks" => ("SOCKS", "socks"),
+        _ => return None,
+    };
+    if !scutil.enabled(&format!("{prefix}Enable")) {
+        return None;
     }
+    let host = scutil.value(&format!("{prefix}Proxy"))?;
+    let port = scutil.value(&format!("{prefix}Port"))?;
+    let proxy = port
+        .parse()
+        .map_err(|_| format!("invalid port `{port}`"))
+        .and_then(|port| ProxyUrl::new(scheme, host, Some(port)));
+    Some(proxy.map_err(|e| format!("Invalid {prefix}Proxy `{host}:{port}`: {e}")))
+}
 
-    if items.is_empty() {
-        None
+fn get_proxy_auto_config_url(scutil: &Scutil) -> Option<String> {
+    if scutil.enabled("ProxyAutoConfigEnable") {
+        scutil.value("ProxyAutoConfigURLString").map(str::to_string)
     } else {
-        Some(items)
+        None
     }
 }
+
+fn get_proxy_bypass_list(scutil: &Scutil) -> Option<String> {
+    let exceptions = scutil.arrays.get("ExceptionsList")?;
+    // IPv6 entries may be bracketed or carry a zone ID, which no_proxy cannot match
+    let entries = exceptions
+        .iter()
+        .map(|it| no_proxy::strip_ipv6(it).map_or_else(|| it.to_string(), |ip| ip.to_string()))
+        .collect::<Vec<_>>();
+    (!entries.is_empty()).then(|| entries.join(","))
+}
@@ -1,32 +1,154 @@
 use std::borrow::Cow;
 use std::collections::BTreeSet;
+use std::net::Ipv6Addr;
 
 use colored::Colorize;
 
-use super::ProxyList;
+use super::{Detection, ProxyList, RawList};
+use crate::{cli::QueryOptions, network::NetworkInfo, no_proxy, proxy_url::ProxyUrl, utils};
 
 const PROXY_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings";
 
-pub fn get_proxies(force_socks5h: bool) -> ProxyList<'static> {
-    from_registry(force_socks5h)
+pub fn get_proxies(query_options: &QueryOptions, force_socks5h: bool) -> Result<Detection, String> {
+    from_registry(query_options, force_socks5h)
 }
 
-fn from_registry(force_socks5h: bool) -> ProxyList<'static> {
+fn from_registry(query_options: &QueryOptions, force_socks5h: bool) -> Result<Detection, String> {
     // Get system proxy
     let hkcu = winreg::RegKey::predef(winreg::enums::HKEY_CURRENT_USER);
-    let settings = hkcu.open_subkey(PROXY_KEY).unwrap();
+    let settings = hkcu
+        .open_subkey(PROXY_KEY)
+        .map_err(|e| format!("Failed to open HKCU\\{PROXY_KEY}: {e}"))?;
+
+    // Automatic configuration takes precedence over the manual proxy settings,
+    // and like Windows the script is tried when detection fails
+    let auto_detect: u32 = settings.get_value("AutoDetect").unwrap_or_default();
+    let auto_config_url: String = settings.get_value("AutoConfigURL").unwrap_or_default();
+    if auto_detect == 1 {
+        match super::from_wpad(&search_domains(), query_options) {
+            Ok(proxies) => return Ok(proxies),
+            Err(e) => eprintln!(
+                "{}: Automatic proxy detection failed: {}",
+                "Warning".red(),
+                utils::redact(&e)
+            ),
+        }
+    }
+    if !auto_config_url.is_empty() {
+        match super::from_pac(&auto_config_url, query_options) {
+            Ok(proxies) => return Ok(proxies),
+            Err(e) => eprintln!(
+                "{}: Automatic proxy configuration failed: {}",
+                "Warning".red(),
+                utils::redact(&e)
+            ),
+        }
+    }
+
     let proxy_enabled: u32 = settings.get_value("ProxyEnable").unwrap_or_default();
     let proxy_server: String = settings.get_value("ProxyServer").unwrap_or_default();
     let proxy_override: String = settings.get_value("ProxyOverride").unwrap_or_default();
     if !proxy_enabled.eq(&1) {
-        return Default::default();
+        return Ok(Default::default());
     }
 
     let mut proxy_list = parse_proxy_server(&proxy_server, force_socks5h);
     let no_proxy = parse_no_proxy(&proxy_override);
 
     proxy_list.no = Cow::Owned(no_proxy);
-    proxy_list
+
+    let raw = |set: bool, key: &str, raw: &str| match set {
+        true => format!("{key}={raw}"),
+        false => String::new(),
+    };
+    let raw = RawList {
+        http: raw(proxy_list.http.is_some(), "ProxyServer", &proxy_server),
+        https: raw(proxy_list.https.is_some(), "ProxyServer", &proxy_server),
+        ftp: raw(proxy_list.ftp.is_some(), "ProxyServer", &proxy_server),
+        all: raw(proxy_list.all.is_some(), "ProxyServer", &proxy_server),
+        no: raw(!proxy_list.no.is_empty(), "ProxyOverride", &proxy_override),
+    };
+    Ok(Detection {
+        proxies: proxy_list,
+        raw,
+        lookup: None,
+    })
+}
+
+/// DNS search domains from the TCP/IP parameters, used for WPAD discovery.
+fn search_domains() -> Vec<String> {
+    const TCPIP_KEY: &str = "SYSTEM\\CurrentControlSet\\Services\\Tcpip\\Parameters";
+
+    let hklm = winreg::RegKey::predef(winreg::enums::HKEY_LOCAL_MACHINE);
+    let Ok(params) = hklm.open_subkey(TCPIP_KEY) else {
+        return Vec::new();
+    };
+
+    let mut domains = Vec::new();
+    for name in ["SearchList", "Domain", "DhcpDomain"] {
+        let value: String = params.get_value(name).unwrap_or_default();
+        for domain in value.split([',', ' ']).filter(|it| !it.is_empty()) {
+            if !domains.iter().any(|it| it == domain) {
+                domains.push(domain.to_string());
+            }
+        }
+    }
+    domains
+}
+
+/// The active network, scraped from `route print` and `netsh`. netsh
+/// translates its labels and states, so SSIDs and interfaces are only found on
+/// English systems; a warning says so when nothing could be read at all.
+pub fn network_info() -> NetworkInfo {
+    let command_output = |program: &str, args: &[&str]| {
+        std::process::Command::new(program)
+            .args(args)
+            .output()
+            .ok()
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+            .unwrap_or_default()
+    };
+
+    // `0.0.0.0  0.0.0.0  <gateway>  <interface>  <metric>`
+    let gateways = command_output("route", &["print", "-4", "0.0.0.0"])
+        .lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["0.0.0.0", "0.0.0.0", gateway, ..] => gateway.parse().ok(),
+                _ => None,
+            },
+        )
+        .collect::<Vec<_>>();
+    // `    SSID                   : CorpWiFi`
+    let ssids = command_output("netsh", &["wlan", "show", "interfaces"])
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("SSID"))
+        .filter_map(|rest| rest.split_once(':'))
+        .map(|(_, ssid)| ssid.trim().to_string())
+        .collect::<Vec<_>>();
+    // `Enabled        Connected      Dedicated        Wi-Fi`
+    let interfaces = command_output("netsh", &["interface", "show", "interface"])
+        .lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [_, "Connected", _, ref name @ ..] if !name.is_empty() => Some(name.join(" ")),
+                _ => None,
+            },
+        )
+        .collect::<Vec<_>>();
+    if gateways.is_empty() && ssids.is_empty() && interfaces.is_empty() {
+        eprintln!(
+            "{}: No gateway, Wi-Fi network or interface found in the output of route and netsh, network rules may not match on a non-English system",
+            "Warning".red()
+        );
+    }
+
+    NetworkInfo {
+        gateways,
+        search_domains: search_domains(),
+        ssids,
+        interfaces,
+    }
 }
 
 fn parse_proxy_server(proxy_server: &str, force_socks5h: bool) -> ProxyList<'static> {
@@ -37,39 +159,58 @@ fn parse_proxy_server(proxy_server: &str, force_socks5h: bool) -> ProxyList<'sta
 
     for proxy in proxy_server.split(";") {
         match proxy.split("=").collect::<Vec<&str>>().as_slice() {
-            ["http", value] => http = value.to_string(),
-            ["https", value] => https = value.to_string(),
-            ["ftp", value] => ftp = value.to_string(),
+            ["http", value] => http = escape_zone_id(value).into_owned(),
+            ["https", value] => https = escape_zone_id(value).into_owned(),
+            ["ftp", value] => ftp = escape_zone_id(value).into_owned(),
             ["socks", value] => {
+                let value = escape_zone_id(value);
                 all = if value.starts_with("socks") {
-                    value.to_string()
+                    value.into_owned()
                 } else {
                     format!("socks://{}", value)
                 }
             }
             [value] => match value.split("://").collect::<Vec<_>>().as_slice() {
                 [protocol, _] => match *protocol {
-                    "http" => http = value.to_string(),
-                    "https" => https = value.to_string(),
-                    "socks" => all = value.to_string(),
-                    _ => eprintln!("Invalid proxy protocol: {}", protocol),
+                    "http" => http = escape_zone_id(value).into_owned(),
+                    "https" => https = escape_zone_id(value).into_owned(),
+                    "socks" => all = escape_zone_id(value).into_owned(),
+                    _ => eprintln!("Invalid proxy protocol: {}", utils::redact(protocol)),
                 },
                 [_] => {
                     if !value.is_empty() {
                         all = add_protocol_prefix(value, force_socks5h);
                     }
                 }
-                _ => eprintln!("Invalid proxy definition: {}", value),
+                _ => eprintln!("Invalid proxy definition: {}", utils::redact(value)),
             },
-            _ => eprintln!("Invalid proxy definition: {}", proxy),
+            _ => eprintln!("Invalid proxy definition: {}", utils::redact(proxy)),
         }
     }
 
+    // Entries like `http=proxy:8080` have no scheme of their own
+    let url = |value: String| {
+        if value.is_empty() {
+            return None;
+        }
+        match ProxyUrl::with_default_scheme(&value, "http") {
+            Ok(url) => Some(url),
+            Err(e) => {
+                eprintln!(
+                    "{}: Invalid proxy `{}`: {}",
+                    "Warning".red(),
+                    utils::redact(&value),
+                    utils::redact(&e)
+                );
+                None
+            }
+        }
+    };
     ProxyList {
-        http: Cow::Owned(http),
-        https: Cow::Owned(https),
-        ftp: Cow::Owned(ftp),
-        all: Cow::Owned(all),
+        http: url(http),
+        https: url(https),
+        ftp: url(ftp),
+        all: url(all),
         no: Cow::Borrowed(""),
     }
 }
@@ -77,6 +218,7 @@ fn parse_proxy_server(proxy_server: &str, force_socks5h: bool) -> ProxyList<'sta
 fn add_protocol_prefix(value: &str, force_socks5h: bool) -> String {
     // Check if it's a bare IP address (no protocol prefix)
     if is_bare_ip_address(value) {
+        let value = escape_zone_id(value);
         if force_socks5h {
             format!("socks5h://{}", value)
         } else {
@@ -87,26 +229,45 @@ fn add_protocol_prefix(value: &str, force_socks5h: bool) -> String {
     }
 }
 
+/// Zone IDs are written `%25` in URLs (RFC 6874), e.g. `[fe80::1%2512]:8080`.
+/// The registry holds raw addresses, so the `%` is always escaped, even in
+/// `[fe80::1%251]` where the zone is interface 251.
+fn escape_zone_id(value: &str) -> Cow<'_, str> {
+    let host = value.find("://").map_or(value, |idx| &value[idx + 3..]);
+    match host.starts_with('[') && host.contains('%') {
+        true => Cow::Owned(value.replacen('%', "%25", 1)),
+        false => Cow::Borrowed(value),
+    }
+}
+
 fn is_bare_ip_address(value: &str) -> bool {
     // Check if it matches IP:port pattern without protocol
     if value.contains("://") {
         return false;
     }
 
-    // Split by colon to get potential IP and port
-    let parts: Vec<&str> = value.split(':').collect();
-    if parts.len() != 2 {
+    // Split at the last colon to get potential IP and port
+    let Some((ip_part, port_part)) = value.rsplit_once(':') else {
         return false;
-    }
-
-    let ip_part = parts[0];
-    let port_part = parts[1];
+    };
 
     // Check if port is a valid number
     if port_part.parse::<u16>().is_err() {
         return false;
     }
 
+    // IPv6 addresses are bracketed and may carry a zone ID, e.g. `[fe80::1%12]`
+    if let Some(ip_part) = ip_part
+        .strip_prefix('[')
+        .and_then(|it| it.strip_suffix(']'))
+    {
+        let (addr, zone) = match ip_part.split_once('%') {
+            Some((addr, zone)) => (addr, Some(zone)),
+            None => (ip_part, None),
+        };
+        return addr.parse::<Ipv6Addr>().is_ok() && zone.is_none_or(|it| !it.is_empty());
+    }
+
     // Check if IP part looks like an IPv4 address
     let ip_parts: Vec<&str> = ip_part.split('.').collect();
     if ip_parts.len() != 4 {
@@ -145,6 +306,10 @@ fn parse_no_proxy(proxy_override: &str) -> String {
                     0 => {}
                     _ => unreachable!("Invalid subnet: {}", subnet),
                 }
+            } else if let Some(subnet) = eval_ipv6(&it) {
+                it = subnet;
+            } else if let Some(addr) = no_proxy::strip_ipv6(&it) {
+                it = addr.to_string();
             } else {
                 // no_proxy variable does not support wildcard
                 let mut tmp = it.as_str();
@@ -199,6 +364,30 @@ fn eval_ipv4(addr: &str) -> Option<u8> {
     Some(subnet as u8)
 }
 
+/// Change an IPv6 wildcard like `fe80::*` or `2001:db8:*:*` to a subnet; only
+/// trailing groups may be wildcards. In `fe80::*` the `::` stands for at least
+/// one zero group, so the subnet is `fe80::/32`.
+fn eval_ipv6(addr: &str) -> Option<String> {
+    if !addr.contains(':') || !addr.ends_with('*') {
+        return None;
+    }
+    let prefix = addr.trim_end_matches(['*', ':']);
+    let zeros = usize::from(addr[prefix.len()..].starts_with("::"));
+    if prefix.is_empty() || prefix.contains(['*', '%']) || prefix.contains("::") {
+        return None;
+    }
+    let groups = prefix.split(':').collect::<Vec<_>>();
+    if groups.len() + zeros > 7
+        || groups
+            .iter()
+            .any(|it| it.is_empty() || it.len() > 4 || u16::from_str_radix(it, 16).is_err())
+    {
+        return None;
+    }
+    let network = format!("{prefix}::").parse::<Ipv6Addr>().ok()?;
+    Some(format!("{}/{}", network, (groups.len() + zeros) * 16))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -215,11 +404,8 @@ mod tests {
         assert_eq!(
             parse_proxy_server(proxy_server, false),
             ProxyList {
-                http: Cow::Borrowed(""),
-                https: Cow::Borrowed(""),
-                ftp: Cow::Borrowed(""),
-                all: Cow::Owned("http://127.0.0.1:8080".to_string()),
-                no: Cow::Borrowed("")
+                all: "http://127.0.0.1:8080".parse().ok(),
+                ..Default::default()
             }
         );
 
@@ -227,11 +413,8 @@ mod tests {
         assert_eq!(
             parse_proxy_server(proxy_server, true),
             ProxyList {
-                http: Cow::Borrowed(""),
-                https: Cow::Borrowed(""),
-                ftp: Cow::Borrowed(""),
-                all: Cow::Owned("socks5h://127.0.0.1:7890".to_string()),
-                no: Cow::Borrowed("")
+                all: "socks5h://127.0.0.1:7890".parse().ok(),
+                ..Default::default()
             }
         );
 
@@ -240,13 +423,41 @@ mod tests {
         assert_eq!(
             parse_proxy_server(proxy_server, false),
             ProxyList {
-                http: Cow::Owned("127.0.0.1:7890".to_string()),
-                https: Cow::Owned("127.0.0.1:7890".to_string()),
-                ftp: Cow::Owned("127.0.0.1:7890".to_string()),
-                all: Cow::Owned("socks://127.0.0.1:7890".to_string()),
+                http: "http://127.0.0.1:7890".parse().ok(),
+                https: "http://127.0.0.1:7890".parse().ok(),
+                ftp: "http://127.0.0.1:7890".parse().ok(),
+                all: "socks://127.0.0.1:7890".parse().ok(),
                 no: Cow::Borrowed("")
             }
         );
+
+        let proxy_server = "[::1]:8080";
+        assert_eq!(
+            parse_proxy_server(proxy_server, false),
+            ProxyList {
+                all: "http://[::1]:8080".parse().ok(),
+                ..Default::default()
+            }
+        );
+
+        let proxy_server = "http=[fe80::1%12]:7890;socks=[::1]:1080";
+        assert_eq!(
+            parse_proxy_server(proxy_server, false),
+            ProxyList {
+                http: "http://[fe80::1%2512]:7890".parse().ok(),
+                all: "socks://[::1]:1080".parse().ok(),
+                ..Default::default()
+            }
+        );
+
+        let proxy_server = "http=proxy.corp:8080;https=pro xy:8080";
+        assert_eq!(
+            parse_proxy_server(proxy_server, false),
+            ProxyList {
+                http: "http://proxy.corp:8080".parse().ok(),
+                ..Default::default()
+            }
+        );
     }
 
     #[test]
@@ -273,6 +484,44 @@ mod tests {
         assert_eq!(parse_no_proxy(proxy_override), "baidu.com,google.com");
     }
 
+    #[test]
+    fn test_parse_no_proxy_ipv6() {
+        let proxy_override = "::1";
+        assert_eq!(parse_no_proxy(proxy_override), "::1");
+
+        let proxy_override = "[::1]";
+        assert_eq!(parse_no_proxy(proxy_override), "::1");
+
+        let proxy_override = "fe80::1%12";
+        assert_eq!(parse_no_proxy(proxy_override), "fe80::1");
+
+        let proxy_override = "fe80::*";
+        assert_eq!(parse_no_proxy(proxy_override), "fe80::/32");
+
+        let proxy_override = "2001:db8:*:*";
+        assert_eq!(parse_no_proxy(proxy_override), "2001:db8::/32");
+
+        let proxy_override = "2001:db8::/32";
+        assert_eq!(parse_no_proxy(proxy_override), "2001:db8::/32");
+
+        let proxy_override = "localhost;10.0.*.*;fe80::*;[::1]";
+        assert_eq!(
+            parse_no_proxy(proxy_override),
+            "10.0.0.0/16,::1,fe80::/32,localhost".to_string()
+        );
+    }
+
+    #[test]
+    fn test_eval_ipv6() {
+        assert_eq!(eval_ipv6("fe80::*"), Some("fe80::/32".to_string()));
+        assert_eq!(eval_ipv6("2001:DB8:*"), Some("2001:db8::/32".to_string()));
+        assert_eq!(eval_ipv6("fe80::1:*"), None);
+        assert_eq!(eval_ipv6("fe80:*:1"), None);
+        assert_eq!(eval_ipv6("10.0.*"), None);
+        assert_eq!(eval_ipv6("*.google.com"), None);
+        assert_eq!(eval_ipv6("::1"), None);
+    }
+
     #[test]
     fn test_is_bare_ip_address() {
         assert!(is_bare_ip_address("127.0.0.1:8080"));
@@ -282,6 +531,12 @@ mod tests {
         assert!(!is_bare_ip_address("proxy.example.com:8080"));
         assert!(!is_bare_ip_address("127.0.0.1"));
         assert!(!is_bare_ip_address("127.0.0.1:abc"));
+        assert!(is_bare_ip_address("[::1]:8080"));
+        assert!(is_bare_ip_address("[fe80::1%12]:8080"));
+        assert!(!is_bare_ip_address("[::1]"));
+        assert!(!is_bare_ip_address("::1:8080"));
+        assert!(!is_bare_ip_address("[fe80::1%]:8080"));
+        assert!(!is_bare_ip_address("http://[::1]:8080"));
     }
 
     #[test]
@@ -302,5 +557,29 @@ mod tests {
             add_protocol_prefix("proxy.example.com:8080", false),
             "proxy.example.com:8080"
         );
+        assert_eq!(
+            add_protocol_prefix("[::1]:7890", false),
+            "http://[::1]:7890"
+        );
+        assert_eq!(
+            add_protocol_prefix("[fe80::1%12]:7890", true),
+            "socks5h://[fe80::1%2512]:7890"
+        );
+    }
+
+    #[test]
+    fn test_escape_zone_id() {
+        assert_eq!(escape_zone_id("[fe80::1%12]:8080"), "[fe80::1%2512]:8080");
+        assert_eq!(escape_zone_id("[fe80::1%25]:8080"), "[fe80::1%2525]:8080");
+        assert_eq!(escape_zone_id("[fe80::1%251]:8080"), "[fe80::1%25251]:8080");
+        assert_eq!(
+            escape_zone_id("[fe80::1%25eth0]:8080"),
+            "[fe80::1%2525eth0]:8080"
+        );
+        assert_eq!(
+            escape_zone_id("socks://[fe80::1%12]:1080"),
+            "socks://[fe80::1%2512]:1080"
+        );
+        assert_eq!(escape_zone_id("[::1]:8080"), "[::1]:8080");
     }
 }